@@ -0,0 +1,26 @@
+//! Feeds arbitrary text to the deserializers, which should never panic. Whatever they accept has
+//! to survive being written and read back in.
+//!
+//! Run with `cargo +nightly fuzz run deserialize`.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use snippets_everywhere::backends::{Backend, Ols, UltiSnips};
+
+fuzz_target!(|input: &str| {
+    for backend in [&UltiSnips::default() as &dyn Backend, &Ols] {
+        let Ok(ir) = backend.deserialize(input) else {
+            continue;
+        };
+        let Ok(first) = backend.serialize(&ir) else {
+            continue;
+        };
+
+        let reread = backend
+            .deserialize(&first)
+            .unwrap_or_else(|err| panic!("couldn't read own output {first:?}: {err}"));
+        let second = backend.serialize(&reread).unwrap();
+        assert_eq!(first, second, "output changed when read back in");
+    }
+});
@@ -0,0 +1,44 @@
+//! Code run around expanding a snippet, instead of inserted by it
+
+use std::fmt;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::body::Language;
+
+/// Code run when something happens to a snippet, like `pre_expand` in UltiSnips.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct Action {
+    pub event: Event,
+    pub language: Language,
+    pub source: String,
+}
+
+/// When an [`Action`] runs.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    /// After the trigger matched, before anything was inserted.
+    PreExpand,
+    /// After the snippet was inserted, before jumping to the first tabstop.
+    PostExpand,
+    /// After each jump to a tabstop.
+    PostJump,
+}
+
+impl Event {
+    pub const ALL: [Event; 3] = [Event::PreExpand, Event::PostExpand, Event::PostJump];
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Event::PreExpand => "before expanding",
+            Event::PostExpand => "after expanding",
+            Event::PostJump => "after jumping",
+        })
+    }
+}
@@ -0,0 +1,124 @@
+#[cfg(test)]
+mod tests;
+
+use anyhow::{Context as _, Result};
+use schemars::{schema::RootSchema, schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    body::Language,
+    diagnostic::Warning,
+    span::{LineIndex, SpannedError},
+    SnippetFile,
+};
+
+use super::Backend;
+
+/// Backend for dumping and loading _the IR_ itself as pretty JSON, to see what another backend
+/// actually parsed or to process snippets with other tools.
+///
+/// Documents carry the [`Ir::VERSION`] of their format and are described by [`Ir::schema`],
+/// which is also checked in as `schema/ir.schema.json`.
+#[derive(Debug)]
+pub struct Ir;
+
+impl Ir {
+    /// Version of the format read and written. Bumped whenever older documents wouldn't load
+    /// anymore or would mean something different.
+    pub const VERSION: u64 = 1;
+
+    /// The JSON Schema of the documents read and written.
+    pub fn schema() -> RootSchema {
+        schema_for!(Document<SnippetFile>)
+    }
+}
+
+impl Backend for Ir {
+    fn name(&self) -> &'static str {
+        "ir"
+    }
+
+    /// Never warns, the IR is read exactly as written.
+    fn deserialize_with_warnings(
+        &self,
+        input: &str,
+        _warnings: &mut Vec<Warning>,
+    ) -> Result<SnippetFile> {
+        deserialize(input).context("error while loading the IR")
+    }
+
+    /// Never warns, the IR can hold everything.
+    fn serialize_with_warnings(
+        &self,
+        snippets: &SnippetFile,
+        _warnings: &mut Vec<Warning>,
+    ) -> Result<String> {
+        let document = Document {
+            version: Ir::VERSION,
+            snippets,
+        };
+        let mut output = serde_json::to_string_pretty(&document)?;
+        output.push('\n');
+        Ok(output)
+    }
+
+    /// Keeps code in any language, it's not run after all.
+    fn languages(&self) -> &'static [Language] {
+        &[
+            Language::Python,
+            Language::Vimscript,
+            Language::Shell,
+            Language::JavaScript,
+        ]
+    }
+}
+
+/// The IR as written to disk, tagged with the version of the format.
+#[derive(Deserialize, Serialize, JsonSchema)]
+#[schemars(title = "SnippetFile")]
+struct Document<T> {
+    /// Version of the format, currently always 1.
+    version: u64,
+    #[serde(flatten)]
+    snippets: T,
+}
+
+#[derive(Debug, Error)]
+enum LoadError {
+    #[error("found no `version` field, which tells what version of the IR format this is")]
+    MissingVersion,
+    #[error(
+        "the IR format version {found} isn't supported, only version {} is",
+        Ir::VERSION
+    )]
+    UnsupportedVersion { found: serde_json::Value },
+}
+
+fn deserialize(input: &str) -> Result<SnippetFile> {
+    let index = LineIndex::new(input);
+    let located = |err: serde_json::Error| {
+        let offset = index.offset(err.line(), err.column());
+        SpannedError::new(index.span(offset..offset), err)
+    };
+    let whole = || index.span(0..input.len());
+
+    // checked first, so a document of another version fails with a helpful message instead of
+    // whatever changed between the versions
+    #[derive(Deserialize)]
+    struct Version {
+        version: Option<serde_json::Value>,
+    }
+    let Version { version } = serde_json::from_str(input).map_err(located)?;
+    match version {
+        None => return Err(SpannedError::new(whole(), LoadError::MissingVersion).into()),
+        Some(found) if found.as_u64() != Some(Ir::VERSION) => {
+            let err = LoadError::UnsupportedVersion { found };
+            return Err(SpannedError::new(whole(), err).into());
+        }
+        Some(_) => (),
+    }
+
+    let document: Document<SnippetFile> = serde_json::from_str(input).map_err(located)?;
+    Ok(document.snippets)
+}
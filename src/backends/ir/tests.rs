@@ -0,0 +1,61 @@
+use std::{env, fs, path::Path};
+
+use crate::{backends::UltiSnips, span::SpannedError};
+
+use super::*;
+
+#[test]
+fn roundtrip_through_ir() {
+    let input = r#"# greek
+priority 10
+context "math()"
+snippet "(\d+)x" "times" rA
+${1:`!p snip.rv = match.group(1)`} \cdot $0
+endsnippet
+
+"#;
+
+    let ir = UltiSnips::default().deserialize(input).unwrap();
+    let dumped = Ir.serialize(&ir).unwrap();
+    assert!(dumped.starts_with("{\n  \"version\": 1,\n"));
+
+    let loaded = Ir.deserialize(&dumped).unwrap();
+    assert_eq!(loaded, ir);
+    assert_eq!(loaded.snippets()[0].span().start.line, 4);
+    assert_eq!(
+        UltiSnips::default().serialize(&loaded).unwrap(),
+        UltiSnips::default().serialize(&ir).unwrap(),
+    );
+}
+
+#[test]
+fn rejects_other_versions() {
+    let version = |input| {
+        let err = Ir.deserialize(input).unwrap_err();
+        err.downcast::<SpannedError>().unwrap().to_string()
+    };
+
+    assert_eq!(
+        version(r#"{"version": 2, "snippets": []}"#),
+        "the IR format version 2 isn't supported, only version 1 is",
+    );
+    assert_eq!(
+        version(r#"{"snippets": []}"#),
+        "found no `version` field, which tells what version of the IR format this is",
+    );
+}
+
+#[test]
+fn schema_is_up_to_date() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("schema/ir.schema.json");
+    let mut generated = serde_json::to_string_pretty(&Ir::schema()).unwrap();
+    generated.push('\n');
+
+    if env::var_os("UPDATE_SCHEMA").is_some() {
+        fs::write(&path, &generated).unwrap();
+    }
+    assert!(
+        fs::read_to_string(&path).unwrap_or_default() == generated,
+        "the checked-in schema is outdated, run the tests with UPDATE_SCHEMA=1 to update it",
+    );
+}
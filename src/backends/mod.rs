@@ -1,8 +1,10 @@
 //! Semi-dynamic backend system
 //!
 //! A backend is a struct implementing [`Backend`], having a name, offering deserialization
-//! (input) abilities through [`Backend::deserialize`] and serialization (output) abilities
-//! through [`Backend::serialize`], using [`SnippetFile`] as the linking part between them.
+//! (input) abilities through [`Backend::deserialize_with_warnings`] and serialization (output)
+//! abilities through [`Backend::serialize_with_warnings`], using [`SnippetFile`] as the linking
+//! part between them. Anything either side can't carry over exactly is reported as a
+//! [`Warning`] instead of silently dropped.
 //!
 //! In order to create a new backend, create a struct implementing [`Backend`] and add it to the
 //! [`all`] method in this module. That's all what's needed, it'll appear on the CLI automatically.
@@ -10,38 +12,73 @@
 //! If a backend happens to support only deserialization or only serialization (or neither, in
 //! which case it's inaccessible though), it's also possible to override [`Backend::name_in`] and
 //! [`Backend::name_out`] respectively and make them return [`None`] instead.
+//!
+//! Backends whose format is tied to the file system, for example by encoding the scope in the
+//! file name or embedding the snippets in another file, can override
+//! [`Backend::scope_from_path`], [`Backend::read`] and [`Backend::write`]. Backends with
+//! knobs of their own can offer them on the CLI through [`Backend::args`] and
+//! [`Backend::configure`].
+
+#[cfg(test)]
+mod tests;
 
+mod ir;
 mod ols;
 mod ultisnips;
 
-use anyhow::Result;
+use std::{fs, path::Path};
+
+use anyhow::{Context, Result};
+use clap::{Arg, ArgMatches};
+pub use ir::Ir;
+use itertools::Itertools;
 pub use ols::Ols;
 pub use ultisnips::UltiSnips;
 
-use crate::SnippetFile;
+use crate::{body::Language, diagnostic::Warning, Snippet, SnippetFile};
 
 /// All registered backends.
 pub fn all() -> Vec<Box<dyn Backend>> {
-    vec![Box::new(Ols), Box::new(UltiSnips)]
+    vec![Box::new(Ir), Box::new(Ols), Box::new(UltiSnips::default())]
 }
 
 /// Offers communication to and from a file format. See the module-level docs for details.
 pub trait Backend: std::fmt::Debug {
-    /// Tries parsing the given input into _the IR_.
+    /// Tries parsing the given input into _the IR_. Whatever the IR only holds on to without
+    /// understanding is reported in `warnings`.
     ///
     /// # Panics
     ///
     /// Panics if the backend doesn't actually support deserializing. Note to the implementor:
     /// Don't forget to also implement [`Backend::name_in`] to return [`None`] in that case.
-    fn deserialize(&self, input: &str) -> Result<SnippetFile>;
+    fn deserialize_with_warnings(
+        &self,
+        input: &str,
+        warnings: &mut Vec<Warning>,
+    ) -> Result<SnippetFile>;
 
-    /// Tries writing _the IR_ into a string.
+    /// Like [`Backend::deserialize_with_warnings`], for when the warnings don't matter.
+    fn deserialize(&self, input: &str) -> Result<SnippetFile> {
+        self.deserialize_with_warnings(input, &mut Vec::new())
+    }
+
+    /// Tries writing _the IR_ into a string. Whatever can't be expressed exactly in this format
+    /// is reported in `warnings`.
     ///
     /// # Panics
     ///
     /// Panics if the backend doesn't actually support serializing. Note to the implementor:
     /// Don't forget to also implement [`Backend::name_out`] to return [`None`] in that case.
-    fn serialize(&self, snippets: &SnippetFile) -> Result<String>;
+    fn serialize_with_warnings(
+        &self,
+        snippets: &SnippetFile,
+        warnings: &mut Vec<Warning>,
+    ) -> Result<String>;
+
+    /// Like [`Backend::serialize_with_warnings`], for when the warnings don't matter.
+    fn serialize(&self, snippets: &SnippetFile) -> Result<String> {
+        self.serialize_with_warnings(snippets, &mut Vec::new())
+    }
 
     /// The name of this backend, ideally an all-lowercase, short identifier.
     fn name(&self) -> &'static str;
@@ -57,4 +94,105 @@ pub trait Backend: std::fmt::Debug {
     fn name_out(&self) -> Option<String> {
         Some(format!("{}-out", self.name()))
     }
+
+    /// Languages this backend can run [`Node::Code`](crate::body::Node::Code) in. Code in any
+    /// other language is left out when serializing, and has to be ported by hand.
+    fn languages(&self) -> &'static [Language] {
+        &[]
+    }
+
+    /// Additional command line arguments configuring this backend. Their names should start
+    /// with the backend's name, so they don't clash with other backends.
+    fn args(&self) -> Vec<Arg> {
+        Vec::new()
+    }
+
+    /// Applies the arguments from [`Backend::args`] the user gave.
+    fn configure(&mut self, _matches: &ArgMatches) {}
+
+    /// Guesses the scope of a file from its path, for formats which store it there. Only
+    /// consulted if [`Backend::deserialize`] didn't find one in the file itself.
+    fn scope_from_path(&self, _path: &Path) -> Option<String> {
+        None
+    }
+
+    /// Reads what [`Backend::deserialize_with_warnings`] should parse from the given path.
+    /// Backends whose snippets live inside another file can override this to extract them,
+    /// spans then point into what this returns.
+    fn read(&self, path: &Path) -> Result<String> {
+        fs::read_to_string(path).with_context(|| {
+            format!(
+                "error reading input for backend `{}` at path {}",
+                self.name(),
+                path.display()
+            )
+        })
+    }
+
+    /// Serializes and writes the snippets to the given path. Backends which can spread
+    /// snippets of different scopes over multiple files can override this.
+    fn write(
+        &self,
+        path: &Path,
+        snippets: &SnippetFile,
+        warnings: &mut Vec<Warning>,
+    ) -> Result<()> {
+        write_serialized(self, path, snippets, warnings)
+    }
+}
+
+/// Serializes the snippets using the backend and writes them to exactly the given path. What
+/// [`Backend::write`] does if not overridden.
+pub fn write_serialized<B: Backend + ?Sized>(
+    backend: &B,
+    path: &Path,
+    snippets: &SnippetFile,
+    warnings: &mut Vec<Warning>,
+) -> Result<()> {
+    let repr = backend
+        .serialize_with_warnings(snippets, warnings)
+        .with_context(|| format!("could not serialize `{}`", path.display()))?;
+
+    fs::write(path, repr).with_context(|| {
+        format!(
+            "error writing output for backend `{}` at path {}",
+            backend.name(),
+            path.display()
+        )
+    })
+}
+
+/// Warns about code in `snippet` that `backend` can't run, which its serializer leaves out.
+pub(crate) fn warn_foreign_code(
+    backend: &dyn Backend,
+    snippet: &Snippet,
+    warnings: &mut Vec<Warning>,
+) {
+    for language in snippet.body.languages() {
+        if !backend.languages().contains(&language) {
+            warnings.push(Warning::new(
+                snippet,
+                format!("{language} code was left out and needs to be ported by hand"),
+                format!("`{}` can't run it", backend.name()),
+            ));
+        }
+    }
+}
+
+/// Warns about the extra data of other backends in `snippet`, which only they can write.
+pub(crate) fn warn_foreign_extra(
+    backend: &dyn Backend,
+    snippet: &Snippet,
+    warnings: &mut Vec<Warning>,
+) {
+    for (other, extra) in &snippet.extra {
+        if other != backend.name() && !extra.is_empty() {
+            let keys = extra.keys().map(|key| format!("`{key}`")).join(", ");
+            warnings.push(Warning::new(
+                snippet,
+                format!("the {keys} settings of `{other}` were left out"),
+                format!("`{}` has no place for them", backend.name()),
+            ));
+        }
+    }
 }
@@ -0,0 +1,261 @@
+use std::{borrow::Cow, collections::BTreeMap, ops::Range};
+
+use anyhow::Result;
+use thiserror::Error;
+
+use crate::{
+    body::{Body, BodyBuilder, Language, Node},
+    context::Context,
+    options::{take_letter, Flag},
+    regex::Dialect,
+    span::{LineIndex, Span, SpannedError},
+    trigger::{RegexFlags, TriggerKind},
+    Snippet, SnippetFile,
+};
+
+use super::{
+    modes,
+    scan::{function_replacement, scan},
+    Entry, NAME, OPTIONS,
+};
+
+pub fn deserialize(input: &str) -> Result<SnippetFile> {
+    let index = LineIndex::new(input);
+    let scanned =
+        scan(input).map_err(|(err, offset)| SpannedError::new(index.span(offset..offset), err))?;
+
+    let snippets = scanned
+        .entries
+        .into_iter()
+        .map(|raw| {
+            let span = index.span_of(raw.source);
+            let function = function_replacement(raw.source);
+            let source = match &function {
+                Some(range) => Cow::Owned(blank_out(raw.source, range.clone())),
+                None => Cow::Borrowed(raw.source),
+            };
+
+            let mut entry: Entry =
+                json5::from_str(&source).map_err(|err| json5_error(err, raw.source, &index))?;
+            entry.function = function.map(|range| raw.source[range].to_string());
+            let snippet = parse_entry(entry).map_err(|err| SpannedError::new(span.clone(), err))?;
+
+            Ok(Snippet {
+                leading: raw.leading,
+                trailing: raw.trailing,
+                span,
+                ..snippet
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok(SnippetFile {
+        header: scanned.header,
+        snippets,
+        footer: scanned.footer,
+        ..Default::default()
+    })
+}
+
+/// Replaces `range` of `source` by an empty string, padded so that everything after it is still
+/// on the same line and column.
+fn blank_out(source: &str, range: Range<usize>) -> String {
+    let padding: String = source[range.clone()]
+        .chars()
+        .skip(2)
+        .map(|ch| if ch == '\n' { '\n' } else { ' ' })
+        .collect();
+    format!(
+        r#"{}""{padding}{}"#,
+        &source[..range.start],
+        &source[range.end..]
+    )
+}
+
+/// Points the error json5 found in `source`, which is one entry, at its place in the whole
+/// input.
+fn json5_error(err: json5::Error, source: &str, index: &LineIndex) -> SpannedError {
+    let json5::Error::Message { msg, location } = err;
+
+    // syntax errors come already rendered by pest, only the last line is the actual message
+    let msg = match msg.rsplit_once("= ") {
+        Some((_, message)) if msg.starts_with(" -->") => message.to_string(),
+        _ => msg,
+    };
+
+    let entry = index.span_of(source);
+    let offset = location.map_or(0, |location| {
+        LineIndex::new(source).offset(location.line, location.column)
+    });
+    let offset = entry.start.offset + offset;
+    SpannedError::new(index.span(offset..offset), msg)
+}
+
+#[derive(Debug, Error)]
+enum ParseError {
+    #[error("unknown regex flag `{flag}` for trigger `{trigger}`")]
+    UnknownRegexFlag { flag: char, trigger: String },
+}
+
+fn parse_entry(entry: Entry) -> Result<Snippet, ParseError> {
+    let mut letters = entry.options.unwrap_or_default();
+    let trigger_kind = parse_trigger_kind(&mut letters, &entry.trigger, entry.flags.as_deref())?;
+    let contexts = parse_contexts(&mut letters);
+    let options = OPTIONS.parse(&letters);
+
+    let mut extra = BTreeMap::new();
+    if !entry.extra.is_empty() {
+        extra.insert(NAME.to_string(), entry.extra);
+    }
+
+    Ok(Snippet {
+        trigger: entry.trigger,
+        trigger_kind,
+        body: match entry.function {
+            Some(source) => Body(vec![Node::Code {
+                language: Language::JavaScript,
+                source,
+            }]),
+            None => parse_body(
+                &entry.replacement,
+                options.has(Flag::Visual),
+                trigger_kind.is_regex(),
+            ),
+        },
+        options,
+        contexts,
+        actions: Vec::new(),
+        description: entry.description,
+        priority: entry.priority,
+        scope: None,
+        leading: Vec::new(),
+        trailing: Vec::new(),
+        extra,
+        span: Span::default(),
+    })
+}
+
+/// Takes the letters deciding the trigger kind out of the option letters.
+fn parse_trigger_kind(
+    letters: &mut String,
+    trigger: &str,
+    flags: Option<&str>,
+) -> Result<TriggerKind, ParseError> {
+    let kind = if take_letter(letters, 'r') {
+        let flags = RegexFlags::from_letters(flags.unwrap_or_default()).map_err(|flag| {
+            ParseError::UnknownRegexFlag {
+                flag,
+                trigger: trigger.to_string(),
+            }
+        })?;
+        TriggerKind::Regex(RegexFlags {
+            dialect: Some(Dialect::JavaScript),
+            ..flags
+        })
+    } else if take_letter(letters, 'w') {
+        TriggerKind::Word
+    } else {
+        // OLS snippets expand anywhere by default
+        TriggerKind::InWord
+    };
+
+    Ok(kind)
+}
+
+/// Takes the mode letters out of the option letters.
+fn parse_contexts(letters: &mut String) -> Vec<Context> {
+    modes()
+        .into_iter()
+        .filter_map(|(letter, context)| take_letter(letters, letter).then_some(context))
+        .collect()
+}
+
+/// Parses a replacement. `${VISUAL}` only means the selection in `visual` snippets, and
+/// `[[0]]` only a capture group in `regex` ones, everywhere else they're inserted as-is.
+pub(super) fn parse_body(input: &str, visual: bool, regex: bool) -> Body {
+    // OLS has no escaping at all, anything that doesn't look like a tabstop is text
+    let mut builder = BodyBuilder::default();
+    let mut rest = input;
+
+    while let Some(start) = rest.find(['$', '[']) {
+        builder.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let parsed = match rest.as_bytes()[0] {
+            b'$' => parse_dollar(rest, visual),
+            _ if regex => parse_capture(rest),
+            _ => None,
+        };
+        match parsed {
+            Some((node, len)) => {
+                builder.push(node);
+                rest = &rest[len..];
+            }
+            None => {
+                builder.push_char(rest.as_bytes()[0] as char);
+                rest = &rest[1..];
+            }
+        }
+    }
+    builder.push_str(rest);
+
+    let mut body = builder.finish();
+    body.resolve_mirrors();
+    body
+}
+
+/// Parses the `[[n]]` at the start of `input`, returning it and how many bytes it spans.
+fn parse_capture(input: &str) -> Option<(Node, usize)> {
+    let braced = input.strip_prefix("[[")?;
+    let (group, rest) = parse_index(braced)?;
+    let rest = rest.strip_prefix("]]")?;
+    // OLS counts groups from 0, the IR like the regex does
+    Some((
+        Node::Capture(group.checked_add(1)?),
+        input.len() - rest.len(),
+    ))
+}
+
+/// Parses the tabstop at the start of `input`, returning it and how many bytes it spans.
+fn parse_dollar(input: &str, visual: bool) -> Option<(Node, usize)> {
+    let Some(braced) = input.strip_prefix("${") else {
+        let (index, rest) = parse_index(&input[1..])?;
+        return Some((tabstop(index), input.len() - rest.len()));
+    };
+
+    if visual && braced.starts_with("VISUAL}") {
+        let node = Node::Visual {
+            default: None,
+            transform: None,
+        };
+        return Some((node, "${VISUAL}".len()));
+    }
+
+    let (index, rest) = parse_index(braced)?;
+    let (node, rest) = if let Some(rest) = rest.strip_prefix('}') {
+        (tabstop(index), rest)
+    } else {
+        let rest = rest.strip_prefix(':')?;
+        let end = rest.find('}')?;
+        let default = Body::text(&rest[..end]);
+        (Node::Placeholder { index, default }, &rest[end + 1..])
+    };
+
+    Some((node, input.len() - rest.len()))
+}
+
+fn parse_index(input: &str) -> Option<(u32, &str)> {
+    let digits = input
+        .find(|ch: char| !ch.is_ascii_digit())
+        .unwrap_or(input.len());
+    let index = input[..digits].parse().ok()?;
+    Some((index, &input[digits..]))
+}
+
+fn tabstop(index: u32) -> Node {
+    if index == 0 {
+        Node::Final
+    } else {
+        Node::Tabstop(index)
+    }
+}
@@ -1,10 +1,28 @@
-use anyhow::Context;
+#[cfg(test)]
+mod tests;
 
-use crate::{Snippet, SnippetFile};
+mod de;
+mod scan;
+mod ser;
+mod vault;
+
+use std::path::Path;
+
+use anyhow::Context as _;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    body::Language,
+    context::Context,
+    diagnostic::Warning,
+    options::{Flag, OptionTable, Support},
+    Extra, SnippetFile,
+};
 
 use super::Backend;
 
-/// Backend for de- and serializing [Obsidian LaTeX suite] snippet files.
+/// Backend for de- and serializing [Obsidian LaTeX suite] snippet files. On the file system,
+/// it also reads from and writes to the snippets in the plugin settings of a vault directly.
 ///
 /// [Obsidian LaTeX suite]: https://github.com/artisticat1/obsidian-latex-suite
 #[derive(Debug)]
@@ -12,17 +30,112 @@ pub struct Ols;
 
 impl Backend for Ols {
     fn name(&self) -> &'static str {
-        "ols"
+        NAME
+    }
+
+    fn deserialize_with_warnings(
+        &self,
+        input: &str,
+        warnings: &mut Vec<Warning>,
+    ) -> Result<SnippetFile, anyhow::Error> {
+        let snippets = de::deserialize(input).context("error while parsing OLS snippets")?;
+        for snippet in &snippets {
+            OPTIONS.warn_unknown(snippet, warnings);
+        }
+        Ok(snippets)
+    }
+
+    fn serialize_with_warnings(
+        &self,
+        snippets: &SnippetFile,
+        warnings: &mut Vec<Warning>,
+    ) -> Result<String, anyhow::Error> {
+        ser::serialize(snippets, warnings)
     }
 
-    fn deserialize(&self, input: &str) -> Result<SnippetFile, anyhow::Error> {
-        let snippets: Vec<Snippet> =
-            json5::from_str(input).context("error while parsing OLS snippets")?;
+    /// Also takes a vault or the OLS settings in there, reading the snippets from them.
+    fn read(&self, path: &Path) -> Result<String, anyhow::Error> {
+        vault::read(path)
+    }
 
-        Ok(SnippetFile { snippets })
+    /// Also takes a vault or the OLS settings in there, replacing only the snippets in them.
+    fn write(
+        &self,
+        path: &Path,
+        snippets: &SnippetFile,
+        warnings: &mut Vec<Warning>,
+    ) -> Result<(), anyhow::Error> {
+        let repr = self
+            .serialize_with_warnings(snippets, warnings)
+            .with_context(|| format!("could not serialize `{}`", path.display()))?;
+        vault::write(path, &repr)
     }
 
-    fn serialize(&self, snippets: &SnippetFile) -> Result<String, anyhow::Error> {
-        json5::to_string(&snippets.snippets).map_err(Into::into)
+    /// Only as the whole replacement, OLS calls it with the regex match.
+    fn languages(&self) -> &'static [Language] {
+        &[Language::JavaScript]
     }
 }
+
+const NAME: &str = "ols";
+
+/// How OLS spells each flag, see its README. `r` and `w` are handled by the trigger kind
+/// instead, the mode letters by [`modes`].
+pub static OPTIONS: OptionTable = OptionTable {
+    backend: NAME,
+    entries: &[
+        (Flag::AutoExpand, Support::Letter('A')),
+        (
+            Flag::BeginningOfLine,
+            Support::Unsupported("triggers can only be anchored using a regex"),
+        ),
+        (Flag::Visual, Support::Letter('v')),
+        (
+            Flag::KeepTabs,
+            Support::Unsupported("the replacement is always inserted as-is"),
+        ),
+        (
+            Flag::TrimBeforeJump,
+            Support::Unsupported("whitespace is never touched when jumping"),
+        ),
+        (
+            Flag::TrimWhitespace,
+            Support::Unsupported("the replacement is always inserted as-is"),
+        ),
+    ],
+};
+
+/// The option letters OLS uses to restrict snippets to modes, and their context.
+fn modes() -> [(char, Context); 5] {
+    [
+        ('m', Context::Math),
+        ('n', Context::InlineMath),
+        ('M', Context::DisplayMath),
+        ('t', Context::Text),
+        ('c', Context::Code { language: None }),
+    ]
+}
+
+/// One snippet exactly as it appears in the OLS settings, before its replacement is parsed.
+#[derive(Debug, Deserialize, Serialize)]
+struct Entry {
+    trigger: String,
+    replacement: String,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<String>,
+    /// Regex flags in JavaScript syntax, only meaningful if `options` contains `r`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flags: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    priority: Option<i64>,
+    /// Set if the replacement is a JavaScript function instead of a string, which json5 can't
+    /// handle. `replacement` is empty then.
+    #[serde(skip)]
+    function: Option<String>,
+    /// Keys this version doesn't know about, kept as the snippet's extra data.
+    #[serde(flatten)]
+    extra: Extra,
+}
@@ -0,0 +1,348 @@
+//! Splitting OLS snippet files into their entries while keeping comments
+//!
+//! json5 throws comments away and can't tell where a value started, so the top-level array is
+//! taken apart here first, only handing the entries themselves to json5.
+
+use std::{mem, ops::Range};
+
+use thiserror::Error;
+
+use crate::trivia::{self, Trivia};
+
+/// The top-level array, with each entry still unparsed.
+#[derive(Debug, Default)]
+pub struct Scanned<'a> {
+    /// Comments before the opening `[`.
+    pub header: Vec<Trivia>,
+    pub entries: Vec<RawEntry<'a>>,
+    /// Comments after the last entry.
+    pub footer: Vec<Trivia>,
+}
+
+/// One element of the top-level array, not parsed yet.
+#[derive(Debug)]
+pub struct RawEntry<'a> {
+    pub source: &'a str,
+    pub leading: Vec<Trivia>,
+    pub trailing: Vec<Trivia>,
+}
+
+#[derive(Debug, Error)]
+pub enum ScanError {
+    #[error("expected the snippets to be in an array starting with `[`")]
+    ExpectedArray,
+    #[error("the array is never closed by a `]`")]
+    UnclosedArray,
+    #[error("expected `,` or `]` after an entry")]
+    ExpectedSeparator,
+    #[error("an entry is never closed")]
+    UnclosedEntry,
+    #[error("a string is never closed")]
+    UnterminatedString,
+    #[error("a `/*` comment is never closed by `*/`")]
+    UnterminatedComment,
+    #[error("unexpected content after the closing `]`")]
+    TrailingContent,
+}
+
+/// Takes the top-level array apart. On error, also returns the byte offset where it happened.
+pub fn scan(input: &str) -> Result<Scanned<'_>, (ScanError, usize)> {
+    let mut scanner = Scanner {
+        input,
+        pos: if input.starts_with('\u{feff}') { 3 } else { 0 },
+    };
+
+    scanner.array().map_err(|err| (err, scanner.pos))
+}
+
+/// Finds the byte range of a JavaScript function given as `replacement` in `entry`. OLS
+/// evaluates its settings as JavaScript, json5 only knows strings. Returns [`None`] if the
+/// replacement is a string, or if `entry` is broken, json5 can complain about that.
+pub fn function_replacement(entry: &str) -> Option<Range<usize>> {
+    let mut scanner = Scanner {
+        input: entry,
+        pos: 0,
+    };
+
+    scanner.trivia().ok()?;
+    if !scanner.eat(b'{') {
+        return None;
+    }
+
+    loop {
+        scanner.trivia().ok()?;
+        let key = scanner.key()?;
+        scanner.trivia().ok()?;
+        if !scanner.eat(b':') {
+            return None;
+        }
+        scanner.trivia().ok()?;
+
+        let start = scanner.pos;
+        let is_string = matches!(scanner.peek(), Some(b'"' | b'\''));
+        scanner.expression().ok()?;
+        if key == "replacement" && !is_string {
+            // nothing at all isn't a function, but something for json5 to complain about
+            let end = start + entry[start..scanner.pos].trim_end().len();
+            return (end > start).then_some(start..end);
+        }
+
+        scanner.trivia().ok()?;
+        if !scanner.eat(b',') {
+            return None;
+        }
+    }
+}
+
+struct Scanner<'a> {
+    input: &'a str,
+    /// Byte offset into `input`. Can be in the middle of a char while skipping over a value, but
+    /// all places slicing happens at are ASCII. On errors, points at whatever wasn't closed or
+    /// wasn't expected.
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn array(&mut self) -> Result<Scanned<'a>, ScanError> {
+        let (mut header, rest) = self.trivia()?;
+        header.extend(rest);
+        if !self.eat(b'[') {
+            return Err(ScanError::ExpectedArray);
+        }
+
+        let mut entries = Vec::new();
+        let (mut leading, rest) = self.trivia()?;
+        leading.extend(rest);
+
+        loop {
+            match self.peek() {
+                None => return Err(ScanError::UnclosedArray),
+                Some(b']') => {
+                    self.pos += 1;
+                    break;
+                }
+                Some(_) => (),
+            }
+
+            let offset = self.pos;
+            self.value()?;
+            let source = &self.input[offset..self.pos];
+
+            let (mut trailing, rest) = self.trivia()?;
+
+            let next_leading = if self.eat(b',') {
+                // comments on the same line as the comma are still about this entry
+                let (same_line, after) = self.trivia()?;
+                trailing.extend(rest);
+                trailing.extend(same_line);
+                after
+            } else if self.peek() == Some(b']') {
+                rest
+            } else {
+                return Err(ScanError::ExpectedSeparator);
+            };
+
+            entries.push(RawEntry {
+                source,
+                leading: mem::replace(&mut leading, next_leading),
+                trailing,
+            });
+        }
+
+        // whatever's left over is after the last entry
+        let mut footer = leading;
+        let (same_line, rest) = self.trivia()?;
+        footer.extend(same_line);
+        footer.extend(rest);
+        while footer.last() == Some(&Trivia::Blank) {
+            footer.pop();
+        }
+
+        if self.pos != self.input.len() {
+            return Err(ScanError::TrailingContent);
+        }
+
+        Ok(Scanned {
+            header,
+            entries,
+            footer,
+        })
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.pos).copied()
+    }
+
+    fn peek_second(&self) -> Option<u8> {
+        self.input.as_bytes().get(self.pos + 1).copied()
+    }
+
+    fn eat(&mut self, expected: u8) -> bool {
+        let matches = self.peek() == Some(expected);
+        if matches {
+            self.pos += 1;
+        }
+        matches
+    }
+
+    /// Skips whitespace and comments. Returns the comments still on the line the scanner was
+    /// on, and then everything after the first line break.
+    fn trivia(&mut self) -> Result<(Vec<Trivia>, Vec<Trivia>), ScanError> {
+        let mut same_line = Vec::new();
+        let mut rest = Vec::new();
+        let mut seen_newline = false;
+        let mut newlines_since_content = 0;
+
+        loop {
+            match (self.peek(), self.peek_second()) {
+                (Some(b'\n'), _) => {
+                    self.pos += 1;
+                    seen_newline = true;
+                    newlines_since_content += 1;
+                    if newlines_since_content >= 2 {
+                        rest.push(Trivia::Blank);
+                    }
+                }
+                (Some(b'/'), Some(b'/' | b'*')) => {
+                    let comments = self.comment()?;
+                    newlines_since_content = 0;
+                    if seen_newline {
+                        rest.extend(comments);
+                    } else {
+                        same_line.extend(comments);
+                    }
+                }
+                (Some(ch), _) if ch.is_ascii_whitespace() => self.pos += 1,
+                _ => return Ok((same_line, rest)),
+            }
+        }
+    }
+
+    /// Skips one comment, returning one [`Trivia::Comment`] per non-empty line of it.
+    fn comment(&mut self) -> Result<Vec<Trivia>, ScanError> {
+        let rest = &self.input[self.pos + 2..];
+
+        if self.peek_second() == Some(b'/') {
+            let len = rest.find('\n').unwrap_or(rest.len());
+            self.pos += 2 + len;
+            return Ok(vec![trivia::comment(&rest[..len])]);
+        }
+
+        let len = rest.find("*/").ok_or(ScanError::UnterminatedComment)?;
+        self.pos += 2 + len + 2;
+        Ok(rest[..len]
+            .lines()
+            .map(|line| {
+                let line = line.trim();
+                line.strip_prefix('*').unwrap_or(line)
+            })
+            .filter(|line| !line.trim().is_empty())
+            .map(trivia::comment)
+            .collect())
+    }
+
+    /// Skips one value including everything nested in it, without validating it.
+    fn value(&mut self) -> Result<(), ScanError> {
+        let start = self.pos;
+        let mut depth = 0_usize;
+
+        loop {
+            match (self.peek(), self.peek_second()) {
+                (None, _) if depth == 0 => return Ok(()),
+                (None, _) => {
+                    self.pos = start;
+                    return Err(ScanError::UnclosedEntry);
+                }
+                (Some(quote @ (b'"' | b'\'')), _) => self.string(quote)?,
+                (Some(b'/'), Some(b'/' | b'*')) => {
+                    self.comment()?;
+                }
+                (Some(b'{' | b'['), _) => {
+                    depth += 1;
+                    self.pos += 1;
+                }
+                (Some(b'}' | b']'), _) => {
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                    depth -= 1;
+                    self.pos += 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                (Some(b','), _) if depth == 0 => return Ok(()),
+                (Some(ch), _) if depth == 0 && ch.is_ascii_whitespace() => return Ok(()),
+                _ => self.pos += 1,
+            }
+        }
+    }
+
+    /// Skips an object key, quoted or not, and returns it without quotes.
+    fn key(&mut self) -> Option<&'a str> {
+        let start = self.pos;
+        match self.peek()? {
+            quote @ (b'"' | b'\'') => {
+                self.string(quote).ok()?;
+                Some(&self.input[start + 1..self.pos - 1])
+            }
+            _ => {
+                let len = self.input[start..]
+                    .find(|ch: char| !(ch.is_alphanumeric() || ch == '_' || ch == '$'))
+                    .unwrap_or(self.input.len() - start);
+                self.pos += len;
+                (len > 0).then(|| &self.input[start..self.pos])
+            }
+        }
+    }
+
+    /// Skips a JavaScript expression up to the `,` or closing bracket after it. Unlike
+    /// [`Scanner::value`], whitespace doesn't end it.
+    fn expression(&mut self) -> Result<(), ScanError> {
+        let start = self.pos;
+        let mut depth = 0_usize;
+
+        loop {
+            match (self.peek(), self.peek_second()) {
+                (None, _) => {
+                    self.pos = start;
+                    return Err(ScanError::UnclosedEntry);
+                }
+                (Some(quote @ (b'"' | b'\'' | b'`')), _) => self.string(quote)?,
+                (Some(b'/'), Some(b'/' | b'*')) => {
+                    self.comment()?;
+                }
+                (Some(b'(' | b'{' | b'['), _) => {
+                    depth += 1;
+                    self.pos += 1;
+                }
+                (Some(b')' | b'}' | b']' | b','), _) if depth == 0 => return Ok(()),
+                (Some(b')' | b'}' | b']'), _) => {
+                    depth -= 1;
+                    self.pos += 1;
+                }
+                _ => self.pos += 1,
+            }
+        }
+    }
+
+    fn string(&mut self, quote: u8) -> Result<(), ScanError> {
+        let start = self.pos;
+        self.pos += 1;
+
+        loop {
+            match self.peek() {
+                None => {
+                    self.pos = start;
+                    return Err(ScanError::UnterminatedString);
+                }
+                Some(b'\\') => self.pos += 2,
+                Some(ch) if ch == quote => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some(_) => self.pos += 1,
+            }
+        }
+    }
+}
@@ -0,0 +1,338 @@
+use std::{borrow::Cow, fmt::Write};
+
+use anyhow::Context as _;
+use itertools::Itertools;
+
+use crate::{
+    backends::{warn_foreign_code, warn_foreign_extra},
+    body::{Body, Language, Node},
+    context::Context,
+    diagnostic::Warning,
+    options::Flag,
+    regex::{self, Dialect},
+    trigger::TriggerKind,
+    trivia::{write_comment, write_trivia, Trivia},
+    Snippet, SnippetFile,
+};
+
+use super::{de, modes, Entry, Ols, NAME, OPTIONS};
+
+pub fn serialize(snippets: &SnippetFile, warnings: &mut Vec<Warning>) -> anyhow::Result<String> {
+    for global in &snippets.globals {
+        warnings.push(Warning {
+            span: global.span.clone(),
+            ..Warning::for_file(
+                format!("a `global` block of {} code was left out", global.language),
+                "OLS has no code shared between snippets",
+            )
+        });
+    }
+    if !snippets.clears.is_empty() {
+        warnings.push(Warning::for_file(
+            "`clearsnippets` was left out",
+            "OLS can't remove snippets defined elsewhere",
+        ));
+    }
+    let scopes = (snippets.scopes().into_iter().flatten())
+        .map(|scope| format!("`{scope}`"))
+        .join(", ");
+    if !scopes.is_empty() {
+        warnings.push(Warning::for_file(
+            format!("the snippets are available everywhere instead of only in {scopes}"),
+            "OLS has no filetypes",
+        ));
+    }
+    if !snippets.extends.is_empty() {
+        warnings.push(Warning::for_file(
+            format!(
+                "the snippets of {} aren't included",
+                snippets
+                    .extends
+                    .iter()
+                    .map(|filetype| format!("`{filetype}`"))
+                    .join(", "),
+            ),
+            "OLS can't extend other filetypes",
+        ));
+    }
+
+    let entries: Vec<_> = snippets
+        .snippets
+        .iter()
+        .map(|snippet| to_entry(snippet, warnings))
+        .try_collect()?;
+
+    let has_trivia = !snippets.header.is_empty()
+        || !snippets.footer.is_empty()
+        || snippets
+            .snippets
+            .iter()
+            .any(|snippet| !snippet.leading.is_empty() || !snippet.trailing.is_empty());
+    if !has_trivia {
+        let entries: Vec<_> = entries.iter().map(entry_to_string).try_collect()?;
+        return Ok(format!("[{}]", entries.join(",")));
+    }
+
+    // comments need some space, so one entry per line
+    let mut output = String::new();
+    write_trivia(&mut output, &snippets.header, "//", "");
+    output.push_str("[\n");
+
+    for (snippet, entry) in snippets.snippets.iter().zip(&entries) {
+        write_trivia(&mut output, &snippet.leading, "//", "  ");
+        write!(output, "  {},", entry_to_string(entry)?).unwrap();
+
+        let mut trailing = snippet.trailing.as_slice();
+        if let [Trivia::Comment(comment), rest @ ..] = trailing {
+            if !comment.contains('\n') {
+                output.push(' ');
+                write_comment(&mut output, comment, "//");
+                trailing = rest;
+            }
+        }
+        output.push('\n');
+        write_trivia(&mut output, trailing, "//", "  ");
+    }
+
+    write_trivia(&mut output, &snippets.footer, "//", "  ");
+    output.push_str("]\n");
+    Ok(output)
+}
+
+/// json5 can only write the replacement as string, so functions are put in afterwards.
+fn entry_to_string(entry: &Entry) -> json5::Result<String> {
+    let json = json5::to_string(entry)?;
+    Ok(match &entry.function {
+        // keys are the only place quotes aren't escaped, so this can't be in a value
+        Some(function) => json.replacen(
+            r#""replacement":"""#,
+            &format!(r#""replacement":{function}"#),
+            1,
+        ),
+        None => json,
+    })
+}
+
+fn to_entry(snippet: &Snippet, warnings: &mut Vec<Warning>) -> anyhow::Result<Entry> {
+    for action in &snippet.actions {
+        warnings.push(Warning::new(
+            snippet,
+            format!("the action run {} was left out", action.event),
+            "OLS can't run code around expanding",
+        ));
+    }
+
+    let mut replacement = String::new();
+    let function = match snippet.body.0.as_slice() {
+        [Node::Code {
+            language: Language::JavaScript,
+            source,
+        }] => Some(source.clone()),
+        _ => {
+            warn_unsupported(snippet, warnings);
+            warn_nested(snippet, warnings);
+            warn_foreign_code(&Ols, snippet, warnings);
+            let visual = snippet.options.has(Flag::Visual);
+            write_body(&mut replacement, &snippet.body, visual, false);
+            None
+        }
+    };
+
+    warn_foreign_extra(&Ols, snippet, warnings);
+    warn_contexts(snippet, warnings);
+    OPTIONS.warn_skipped(snippet, warnings);
+    let mut options: String = snippet.contexts.iter().filter_map(mode_letter).collect();
+    options.push_str(&OPTIONS.letters(&snippet.options));
+
+    let mut trigger = Cow::Borrowed(snippet.trigger.as_str());
+    let mut flags = None;
+    match snippet.trigger_kind {
+        // OLS has no notion of whitespace-delimited, a word boundary is the closest. Not warned
+        // about, that would hit almost every snippet coming from UltiSnips
+        TriggerKind::Literal | TriggerKind::Word => options.push('w'),
+        TriggerKind::InWord => (),
+        TriggerKind::Regex(regex_flags) => {
+            options.push('r');
+            flags = Some(regex_flags.letters()).filter(|letters| !letters.is_empty());
+            trigger = regex::translate(&snippet.trigger, regex_flags, Dialect::JavaScript)
+                .with_context(|| {
+                    format!(
+                        "the regex trigger `{}` can't be written for JavaScript",
+                        snippet.trigger
+                    )
+                })?;
+        }
+    }
+
+    Ok(Entry {
+        trigger: trigger.into_owned(),
+        replacement,
+        function,
+        options: Some(options).filter(|options| !options.is_empty()),
+        flags,
+        description: snippet.description.clone(),
+        priority: snippet.priority,
+        extra: snippet.extra.get(NAME).cloned().unwrap_or_default(),
+    })
+}
+
+/// Warns about every node OLS can't express, see [`write_body`] for what's written instead.
+fn warn_unsupported(snippet: &Snippet, warnings: &mut Vec<Warning>) {
+    let visual = snippet.options.has(Flag::Visual);
+    let regex = snippet.trigger_kind.is_regex();
+    snippet.body.walk(&mut |node| {
+        let (message, reason) = match node {
+            // OLS would read it back differently
+            Node::Text(text) if de::parse_body(text, visual, regex) != Body::text(text) => (
+                "text that looks like a tabstop or capture group was written as it is".into(),
+                "OLS has no way to escape it",
+            ),
+            Node::Visual { .. } if !visual => (
+                "${VISUAL} was replaced by its default".into(),
+                "OLS only inserts the selection in visual snippets",
+            ),
+            Node::Visual {
+                transform: Some(_), ..
+            } => (
+                "the transformation of ${VISUAL} was left out".into(),
+                "OLS can't transform the selection",
+            ),
+            Node::Choice { index, .. } => (
+                format!("${index} became a placeholder with the first option"),
+                "OLS has no choices",
+            ),
+            Node::Variable(variable) => (
+                format!("${} was left out", variable.lsp_name()),
+                "OLS has no variables",
+            ),
+            Node::Transformation { index, .. } => (
+                format!("${index} became a plain mirror"),
+                "OLS has no transformations",
+            ),
+            // only whole replacements can be functions
+            Node::Code {
+                language: Language::JavaScript,
+                ..
+            } => (
+                "some JavaScript code was left out".into(),
+                "OLS only runs it as the whole replacement",
+            ),
+            Node::Capture(0) => (
+                "the whole regex match was left out".into(),
+                "OLS can only insert groups",
+            ),
+            _ => return,
+        };
+        warnings.push(Warning::new(snippet, message, reason));
+    });
+}
+
+/// Warns about contexts [`mode_letter`] only restricts to roughly, or not at all.
+fn warn_contexts(snippet: &Snippet, warnings: &mut Vec<Warning>) {
+    for context in &snippet.contexts {
+        let (message, reason) = match context {
+            Context::Code { language: Some(_) } => (
+                format!("the {context} context was widened to any code"),
+                "OLS can't tell code blocks of different languages apart",
+            ),
+            Context::Expression { .. } => (
+                format!("the {context} context was left out"),
+                "OLS can't evaluate expressions",
+            ),
+            _ => continue,
+        };
+        warnings.push(Warning::new(snippet, message, reason));
+    }
+}
+
+/// Warns about the tabstops in placeholders, which [`write_body`] leaves out.
+fn warn_nested(snippet: &Snippet, warnings: &mut Vec<Warning>) {
+    for node in &snippet.body.0 {
+        let Node::Placeholder {
+            index: outer,
+            default,
+        } = node
+        else {
+            continue;
+        };
+
+        default.walk(&mut |node| {
+            let message = match node {
+                Node::Tabstop(index) | Node::Mirror(index) | Node::Transformation { index, .. } => {
+                    format!("${index} inside ${outer} was left out")
+                }
+                Node::Final => format!("$0 inside ${outer} was left out"),
+                Node::Placeholder { index, .. } => {
+                    format!("${index} inside ${outer} became plain text")
+                }
+                _ => return,
+            };
+            warnings.push(Warning::new(snippet, message, "OLS can't nest tabstops"));
+        });
+    }
+}
+
+/// Returns the letter restricting a snippet to the given context, if OLS has one for it.
+fn mode_letter(context: &Context) -> Option<char> {
+    match context {
+        // OLS can't tell code blocks of different languages apart
+        Context::Code { .. } => Some('c'),
+        context => modes()
+            .into_iter()
+            .find_map(|(letter, mode)| (mode == *context).then_some(letter)),
+    }
+}
+
+/// Writes the body in OLS syntax. `visual` says if the snippet is a visual one, as only those
+/// have a selection to insert. OLS can't nest placeholders, so if `nested`, only the text the
+/// user would see is written.
+fn write_body(output: &mut String, body: &Body, visual: bool, nested: bool) {
+    let mut nodes = body.0.iter().peekable();
+
+    while let Some(node) = nodes.next() {
+        let digit_follows = matches!(
+            nodes.peek(),
+            Some(Node::Text(text)) if text.starts_with(|ch: char| ch.is_ascii_digit()),
+        );
+
+        match node {
+            Node::Text(text) => output.push_str(text),
+            Node::Tabstop(_) | Node::Mirror(_) | Node::Transformation { .. } | Node::Final
+                if nested => {}
+            Node::Tabstop(index) | Node::Mirror(index) | Node::Transformation { index, .. } => {
+                write_index(output, *index, digit_follows)
+            }
+            Node::Final => write_index(output, 0, digit_follows),
+            Node::Choice { options, .. } if nested => {
+                output.push_str(options.first().map_or("", String::as_str))
+            }
+            Node::Choice { index, options } => match options.first() {
+                Some(first) => write!(output, "${{{index}:{first}}}").unwrap(),
+                None => write_index(output, *index, digit_follows),
+            },
+            Node::Placeholder { default, .. } if nested => {
+                write_body(output, default, visual, true)
+            }
+            Node::Placeholder { index, default } => {
+                write!(output, "${{{index}:").unwrap();
+                write_body(output, default, visual, true);
+                write!(output, "}}").unwrap();
+            }
+            // OLS can't transform the selection
+            Node::Visual { .. } if visual => write!(output, "${{VISUAL}}").unwrap(),
+            // nothing selected in non-visual snippets, so it's always the default
+            Node::Visual { default, .. } => output.push_str(default.as_deref().unwrap_or_default()),
+            Node::Variable(_) | Node::Capture(0) | Node::Code { .. } => (),
+            // OLS counts groups from 0
+            Node::Capture(group) => write!(output, "[[{}]]", group - 1).unwrap(),
+        }
+    }
+}
+
+fn write_index(output: &mut String, index: u32, braced: bool) {
+    if braced {
+        write!(output, "${{{index}}}").unwrap();
+    } else {
+        write!(output, "${index}").unwrap();
+    }
+}
@@ -0,0 +1,459 @@
+use std::fs;
+
+use crate::{
+    body::{Body, Language, Node},
+    context::Context,
+    options::Flag,
+    regex::Dialect,
+    span::SpannedError,
+    trigger::{RegexFlags, TriggerKind},
+    trivia::Trivia,
+    Snippet,
+};
+
+use super::*;
+use crate::backends::UltiSnips;
+
+#[test]
+fn deserialize_body() {
+    let input =
+        r#"[{trigger: "dm", replacement: "\\[\n${0:$1} $$ $1 ${VISUAL}\n\\]", options: "v"}]"#;
+
+    let ir = Ols.deserialize(input).unwrap();
+    assert_eq!(
+        ir.snippets[0].body,
+        Body(vec![
+            Node::Text("\\[\n".to_string()),
+            Node::Placeholder {
+                index: 0,
+                default: Body::text("$1"),
+            },
+            Node::Text(" $$ ".to_string()),
+            Node::Tabstop(1),
+            Node::Text(" ".to_string()),
+            Node::Visual {
+                default: None,
+                transform: None,
+            },
+            Node::Text("\n\\]".to_string()),
+        ]),
+    );
+}
+
+#[test]
+fn roundtrip_body() {
+    let input = r#"[{"trigger":"sq","replacement":"\\sqrt{${1:x}}$2 $1$0"}]"#;
+
+    let ir = Ols.deserialize(input).unwrap();
+    assert_eq!(Ols.serialize(&ir).unwrap(), input);
+}
+
+#[test]
+fn deserialize_trigger_kinds() {
+    let input = r#"[
+        {trigger: "a", replacement: ""},
+        {trigger: "b", replacement: "", options: "mw"},
+        {trigger: "([a-z])hat", replacement: "", options: "rA", flags: "iu"},
+    ]"#;
+
+    let kinds: Vec<_> = Ols
+        .deserialize(input)
+        .unwrap()
+        .snippets
+        .into_iter()
+        .map(|snippet| (snippet.trigger_kind, Vec::from_iter(snippet.options.flags)))
+        .collect();
+    assert_eq!(
+        kinds,
+        [
+            (TriggerKind::InWord, vec![]),
+            (TriggerKind::Word, vec![]),
+            (
+                TriggerKind::Regex(RegexFlags {
+                    ignore_case: true,
+                    unicode: true,
+                    dialect: Some(Dialect::JavaScript),
+                    ..Default::default()
+                }),
+                vec![Flag::AutoExpand],
+            ),
+        ],
+    );
+}
+
+#[test]
+fn roundtrip_modes() {
+    let input = r#"[{"trigger":"dm","replacement":"","options":"mtcA"}]"#;
+
+    let ir = Ols.deserialize(input).unwrap();
+    assert_eq!(
+        ir.snippets[0].contexts,
+        [
+            Context::Math,
+            Context::Text,
+            Context::Code { language: None },
+        ],
+    );
+    assert_eq!(Ols.serialize(&ir).unwrap(), input);
+}
+
+#[test]
+fn deserialize_unknown_regex_flag() {
+    let input = r#"[{trigger: "a+", replacement: "", options: "r", flags: "q"}]"#;
+    assert!(Ols.deserialize(input).is_err());
+}
+
+#[test]
+fn option_table_covers_all_flags() {
+    for flag in Flag::ALL {
+        assert!(OPTIONS.support(flag).is_some(), "{flag:?} missing");
+    }
+}
+
+#[test]
+fn roundtrip_comments() {
+    let input = r#"// greek letters
+[
+  // lowercase
+  {"trigger":"@a","replacement":"\\alpha"}, // the first one
+  {"trigger":"@b","replacement":"\\beta"},
+
+  // misc
+  {"trigger":"hi","replacement":"hello"},
+  // the end
+]
+"#;
+
+    let ir = Ols.deserialize(input).unwrap();
+    assert_eq!(ir.header, [Trivia::Comment("greek letters".to_string())]);
+    assert_eq!(
+        ir.snippets[0].trailing,
+        [Trivia::Comment("the first one".to_string())],
+    );
+    assert_eq!(
+        ir.snippets[2].leading,
+        [Trivia::Blank, Trivia::Comment("misc".to_string())],
+    );
+    assert_eq!(ir.footer, [Trivia::Comment("the end".to_string())]);
+    assert_eq!(Ols.serialize(&ir).unwrap(), input);
+}
+
+#[test]
+fn deserialize_block_comment_and_trailing_entry() {
+    let input = r#"[
+        /*
+         * some
+         * explanation
+         */
+        {trigger: "a", replacement: "b /* not a comment */"}
+    ]"#;
+
+    let ir = Ols.deserialize(input).unwrap();
+    let snippet = &ir.snippets[0];
+    assert_eq!(
+        snippet.leading,
+        [
+            Trivia::Comment("some".to_string()),
+            Trivia::Comment("explanation".to_string()),
+        ],
+    );
+    assert_eq!(snippet.body, Body::text("b /* not a comment */"));
+}
+
+#[test]
+fn errors_point_at_their_source() {
+    let location = |input| {
+        let err = Ols.deserialize(input).unwrap_err();
+        let span = &err.downcast_ref::<SpannedError>().unwrap().span;
+        (span.start.line, span.start.column)
+    };
+
+    // inside an entry, found by json5
+    assert_eq!(location("[\n  {trigger: 'a', replacement: },\n]"), (2, 31));
+    // at the opening quote of a string never closed
+    assert_eq!(location("[\n  {trigger: 'a\n"), (2, 13));
+    // at the entry as a whole
+    assert_eq!(
+        location("[{trigger: 'a', replacement: 'b', options: 'r', flags: 'q'}]"),
+        (1, 2),
+    );
+}
+
+#[test]
+fn deserialize_records_spans() {
+    let input = "[\n  {trigger: 'a', replacement: 'b'},\n  {\n    trigger: 'c',\n    replacement: 'd',\n  },\n]";
+
+    let ir = Ols.deserialize(input).unwrap();
+    let span = &ir.snippets[1].span;
+    assert_eq!(
+        (
+            span.start.line,
+            span.start.column,
+            span.end.line,
+            span.end.column
+        ),
+        (3, 3, 6, 4),
+    );
+}
+
+#[test]
+fn roundtrip_unknown_keys() {
+    let input = r#"[{"trigger":"a","replacement":"b","priority":2,"color":"red","meta":{"tags":["x"],"weight":1.5}}]"#;
+
+    let ir = Ols.deserialize(input).unwrap();
+    let snippet = &ir.snippets[0];
+    assert_eq!(snippet.priority, Some(2));
+    assert_eq!(snippet.extra["ols"]["color"], "red");
+    assert_eq!(Ols.serialize(&ir).unwrap(), input);
+
+    // other backends leave it alone
+    assert!(!UltiSnips::default().serialize(&ir).unwrap().contains("red"));
+}
+
+#[test]
+fn visual_only_in_visual_snippets() {
+    let input = r#"[{trigger: "a", replacement: "${VISUAL}"}]"#;
+    let ir = Ols.deserialize(input).unwrap();
+    assert_eq!(ir.snippets[0].body, Body::text("${VISUAL}"));
+
+    // without a selection, the default is all that's left, which is worth a warning
+    let converted = crate::convert(
+        "snippet b\n(${VISUAL:x/a/b/g})\nendsnippet\n",
+        &UltiSnips::default(),
+        &Ols,
+    )
+    .unwrap();
+    assert!(converted.output.contains(r#""replacement":"(x)""#));
+    assert_eq!(converted.warnings.len(), 1);
+    assert_eq!(
+        converted.warnings[0].message,
+        "${VISUAL} was replaced by its default"
+    );
+
+    // visual snippets keep it as it is
+    let converted = crate::convert(
+        r#"[{trigger: "c", replacement: "${VISUAL}", options: "v"}]"#,
+        &Ols,
+        &Ols,
+    )
+    .unwrap();
+    assert!(converted.warnings.is_empty());
+}
+
+#[test]
+fn serialize_choice_degrades() {
+    let input = r#"{
+        "version": 1,
+        "snippets": [{
+            "trigger": "eq",
+            "trigger_kind": "word",
+            "body": [{"choice": {"index": 1, "options": ["align", "gather"]}}, {"tabstop": 1}]
+        }]
+    }"#;
+
+    let converted = crate::convert(input, &crate::backends::Ir, &Ols).unwrap();
+    assert_eq!(
+        converted.output,
+        r#"[{"trigger":"eq","replacement":"${1:align}$1","options":"w"}]"#,
+    );
+    assert_eq!(converted.warnings.len(), 1);
+}
+
+#[test]
+fn serialize_variable_warns() {
+    let input = r#"snippet date "today"
+`!v strftime('%d')`.`!v strftime('%m')`.
+endsnippet
+"#;
+
+    let converted = crate::convert(input, &UltiSnips::default(), &Ols).unwrap();
+    assert_eq!(
+        converted.output,
+        r#"[{"trigger":"date","replacement":"..","options":"w","description":"today"}]"#,
+    );
+    assert_eq!(converted.warnings.len(), 2);
+    assert!(converted.warnings[0].message.contains("$CURRENT_DATE"));
+}
+
+#[test]
+fn captures_convert_to_ultisnips() {
+    let input = r#"[{"trigger":"([a-z])(\\d)","replacement":"[[0]]_[[1]] [[x]]","options":"r"}]"#;
+
+    let ir = Ols.deserialize(input).unwrap();
+    assert_eq!(
+        ir.snippets[0].body.0[..3],
+        [
+            Node::Capture(1),
+            Node::Text("_".to_string()),
+            Node::Capture(2)
+        ],
+    );
+    assert_eq!(Ols.serialize(&ir).unwrap(), input);
+
+    let ultisnips = UltiSnips::default().serialize(&ir).unwrap();
+    assert!(ultisnips.contains("`!p snip.rv = match.group(1)`_`!p snip.rv = match.group(2)` [[x]]"));
+    let back = UltiSnips::default().deserialize(&ultisnips).unwrap();
+    assert_eq!(back.snippets[0].body, ir.snippets[0].body);
+
+    // only regex snippets have captures
+    let input = r#"[{"trigger":"a","replacement":"[[0]]","options":"w"}]"#;
+    let ir = Ols.deserialize(input).unwrap();
+    assert_eq!(ir.snippets[0].body, Body::text("[[0]]"));
+}
+
+#[test]
+fn roundtrip_function_replacement() {
+    let input = r#"[{"trigger":"([A-Z])","replacement":(match) => { return match[1].toLowerCase(); },"options":"r"}]"#;
+
+    let ir = Ols.deserialize(input).unwrap();
+    assert_eq!(
+        ir.snippets[0].body.0,
+        [Node::Code {
+            language: Language::JavaScript,
+            source: "(match) => { return match[1].toLowerCase(); }".to_string(),
+        }],
+    );
+    assert_eq!(Ols.serialize(&ir).unwrap(), input);
+    assert_eq!(crate::manual_ports(&ir, &UltiSnips::default()).len(), 1);
+}
+
+#[test]
+fn regex_triggers_translate_to_python() {
+    let convert = |trigger: &str, flags: &str| {
+        let input = serde_json::json!([{"trigger": trigger, "replacement": "", "options": "r", "flags": flags}]);
+        crate::convert(&input.to_string(), &Ols, &UltiSnips::default())
+            .map(|converted| converted.output.lines().next().unwrap().to_string())
+    };
+
+    for (trigger, expected) in [
+        (r"(?<n>[a-z])\k<n>", r"(?P<n>[a-z])(?P=n)"),
+        (r"[^]x{,2}\e", r"[\s\S]x\{,2\}e"),
+        (r"\u{1F600}\cJ", r"\U0001f600\x0a"),
+        (r"(?<=ab|c{2})d", r"(?<=ab|c{2})d"),
+        // ASCII-only in JavaScript, but not in Python
+        (r"\d\w+\b\B", r"(?a:\d)(?a:\w)+(?a:\b)(?a:\B)"),
+        (r"[\d\w-]", r"[0-9a-zA-Z0-9_-]"),
+        (
+            r"\s",
+            r"[\t\n\v\f\r \u00a0\u1680\u2000-\u200a\u2028\u2029\u202f\u205f\u3000\ufeff]",
+        ),
+    ] {
+        assert_eq!(
+            convert(trigger, "").unwrap(),
+            format!(r#"snippet "{expected}" "" r"#)
+        );
+    }
+
+    let err = convert(r"(?<=a+)b", "").unwrap_err();
+    assert!(format!("{err:#}").contains("fixed number of characters"));
+    // too wide to even count
+    let err = convert(r"(?<=(aa){9999999999999999999})x", "").unwrap_err();
+    assert!(format!("{err:#}").contains("fixed number of characters"));
+    assert!(convert(r"\p{L}", "u").is_err());
+    assert!(convert(r"[\D]", "").is_err());
+
+    // and back again
+    let input = r#"[{"trigger":"(?<n>\\d\\w)\\k<n>\\b","replacement":"","options":"r"}]"#;
+    let ultisnips = crate::convert(input, &Ols, &UltiSnips::default()).unwrap();
+    let back = crate::convert(&ultisnips.output, &UltiSnips::default(), &Ols).unwrap();
+    let trigger = |input: &str| Ols.deserialize(input).unwrap().snippets[0].trigger.clone();
+    assert_eq!(trigger(&back.output), trigger(input));
+}
+
+#[test]
+fn vault_settings_keep_everything_else() {
+    let dir = tempfile::tempdir().unwrap();
+    let vault = dir.path();
+    let plugin = vault.join(".obsidian/plugins/obsidian-latex-suite");
+    fs::create_dir_all(&plugin).unwrap();
+    let settings = r#"{
+  "snippets": "[\n  {trigger: \"mk\", replacement: \"$$0$\", options: \"tA\"},\n]",
+  "snippetsEnabled": true,
+  "autofractionExcludedEnvs": "[\n\t[\"^{\", \"}\"]\n]",
+  "nested": {"snippets": "untouched"}
+}"#;
+    fs::write(plugin.join("data.json"), settings).unwrap();
+
+    let input = Ols.read(vault).unwrap();
+    assert!(input.starts_with("[\n  {trigger: \"mk\""));
+    let ir = Ols.deserialize(&input).unwrap();
+    assert_eq!(ir.snippets()[0].trigger(), "mk");
+
+    Ols.write(vault, &ir, &mut Vec::new()).unwrap();
+    let written = fs::read_to_string(plugin.join("data.json")).unwrap();
+    let snippets = serde_json::to_string(&Ols.serialize(&ir).unwrap()).unwrap();
+    assert_eq!(
+        written,
+        settings.replace(
+            r#""[\n  {trigger: \"mk\", replacement: \"$$0$\", options: \"tA\"},\n]""#,
+            &snippets,
+        ),
+    );
+    assert_eq!(
+        Ols.read(&plugin.join("data.json")).unwrap(),
+        Ols.serialize(&ir).unwrap()
+    );
+
+    // settings without snippets get them, broken ones are left alone
+    fs::write(
+        plugin.join("data.json"),
+        "{\n  \"snippetsEnabled\": true\n}",
+    )
+    .unwrap();
+    Ols.write(&plugin.join("data.json"), &ir, &mut Vec::new())
+        .unwrap();
+    assert_eq!(
+        fs::read_to_string(plugin.join("data.json")).unwrap(),
+        format!("{{\n  \"snippets\": {snippets},\n  \"snippetsEnabled\": true\n}}"),
+    );
+    fs::write(plugin.join("data.json"), "{\"snippetsEnabled\": tru").unwrap();
+    assert!(Ols.write(vault, &ir, &mut Vec::new()).is_err());
+    assert!(Ols
+        .write(&plugin.join("data.json"), &ir, &mut Vec::new())
+        .is_err());
+    assert_eq!(
+        fs::read_to_string(plugin.join("data.json")).unwrap(),
+        "{\"snippetsEnabled\": tru",
+    );
+}
+
+#[test]
+fn unexpressible_bodies_warn() {
+    let input = "snippet a \"\" b\n\\$1 ${1:x ${2:y}}\nendsnippet\n";
+    let converted = crate::convert(input, &UltiSnips::default(), &Ols).unwrap();
+    assert!(converted.output.contains(r#""replacement":"$1 ${1:x y}""#));
+    let messages: Vec<_> = (converted.warnings.iter())
+        .map(|warning| warning.message.as_str())
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            "text that looks like a tabstop or capture group was written as it is",
+            "$2 inside $1 became plain text",
+            "the beginning-of-line option was left out",
+        ],
+    );
+
+    let input = "snippet \"(\\d)\" \"\" r\n[[0]]`!p snip.rv = match.group(1)`\nendsnippet\n";
+    let converted = crate::convert(input, &UltiSnips::default(), &Ols).unwrap();
+    assert_eq!(converted.warnings.len(), 1);
+    assert_eq!(converted.warnings[0].message, messages[0]);
+}
+
+#[test]
+fn scopes_warn() {
+    let mut ir = SnippetFile::default();
+    ir.push(Snippet::new("a", Body::text("b")));
+    let mut warnings = Vec::new();
+    Ols.serialize_with_warnings(&ir, &mut warnings).unwrap();
+    assert!(warnings.is_empty());
+
+    ir.set_scope(Some("tex".to_string()));
+    ir.push(Snippet::new("c", Body::text("d")).with_scope("markdown"));
+    Ols.serialize_with_warnings(&ir, &mut warnings).unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(
+        warnings[0].message,
+        "the snippets are available everywhere instead of only in `markdown`, `tex`",
+    );
+}
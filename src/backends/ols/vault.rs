@@ -0,0 +1,148 @@
+use std::{
+    fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context as _, Result};
+
+/// Where OLS keeps its settings, relative to the vault.
+const SETTINGS: &str = ".obsidian/plugins/obsidian-latex-suite/data.json";
+
+/// Returns the settings file of the vault if `path` is one, `path` itself otherwise.
+pub fn resolve(path: &Path) -> Result<PathBuf> {
+    if !path.is_dir() {
+        return Ok(path.to_path_buf());
+    }
+
+    let settings = path.join(SETTINGS);
+    if !settings.is_file() {
+        bail!(
+            "{} is a directory, but has no {SETTINGS}. Is OLS installed in that vault?",
+            path.display()
+        );
+    }
+    Ok(settings)
+}
+
+/// Reads the snippets from `path`, which is either a vault, the OLS settings or a plain
+/// snippets file.
+pub fn read(path: &Path) -> Result<String> {
+    let path = resolve(path)?;
+    let input = fs::read_to_string(&path)
+        .with_context(|| format!("error reading OLS snippets at path {}", path.display()))?;
+
+    match snippets_range(&input) {
+        Some(range) => Ok(serde_json::from_str(&input[range])?),
+        None if is_settings(&input) => {
+            bail!("the OLS settings at {} have no snippets", path.display())
+        }
+        None => Ok(input),
+    }
+}
+
+/// Writes the snippets to `path` like [`read`] reads them. If that's the OLS settings, only
+/// the snippets in there are replaced, all other settings stay exactly as they are.
+pub fn write(path: &Path, snippets: &str) -> Result<()> {
+    let path = resolve(path)?;
+
+    let output = match fs::read_to_string(&path) {
+        Ok(existing) if is_settings(&existing) => embed(&existing, snippets)
+            .with_context(|| format!("could not update the OLS settings at {}", path.display()))?,
+        Ok(_) if path.ends_with(SETTINGS) => bail!(
+            "the OLS settings at {} aren't a JSON object, not touching them",
+            path.display()
+        ),
+        Ok(_) => snippets.to_string(),
+        // a file that doesn't exist yet is a plain snippets file to be
+        Err(err) if err.kind() == io::ErrorKind::NotFound => snippets.to_string(),
+        Err(err) => {
+            return Err(err).with_context(|| format!("could not read {}", path.display()));
+        }
+    };
+    fs::write(&path, output)
+        .with_context(|| format!("error writing OLS snippets at path {}", path.display()))
+}
+
+/// If `text` looks like the OLS settings rather than snippets, which are an array.
+fn is_settings(text: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(text).is_ok_and(|value| value.is_object())
+}
+
+/// Puts the snippets into the OLS `settings`, replacing the ones already there or adding them
+/// as first setting.
+fn embed(settings: &str, snippets: &str) -> Result<String> {
+    let value = serde_json::to_string(snippets)?;
+    let mut output = settings.to_string();
+
+    if let Some(range) = snippets_range(settings) {
+        output.replace_range(range, &value);
+        return Ok(output);
+    }
+    if serde_json::from_str::<serde_json::Value>(settings)?
+        .get("snippets")
+        .is_some()
+    {
+        bail!("the `snippets` setting isn't a string");
+    }
+
+    // indented like the setting after it, if there is one
+    let open = settings.find('{').expect("settings are an object") + 1;
+    let after = &settings[open..];
+    let rest = after.trim_start();
+    let space = &after[..after.len() - rest.len()];
+    let entry = if rest.starts_with('}') {
+        format!("\"snippets\": {value}")
+    } else {
+        format!("{space}\"snippets\": {value},")
+    };
+    output.insert_str(open, &entry);
+    Ok(output)
+}
+
+/// Finds the top-level `snippets` string in the OLS settings, returning its byte range with
+/// the quotes. [`None`] if `settings` aren't settings at all, but likely plain snippets.
+pub fn snippets_range(settings: &str) -> Option<Range<usize>> {
+    let parsed: serde_json::Value = serde_json::from_str(settings).ok()?;
+    parsed.get("snippets")?.as_str()?;
+
+    // it's valid JSON, so brackets in strings are the only thing to watch out for
+    let bytes = settings.as_bytes();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => depth -= 1,
+            b'"' => {
+                let end = string_end(bytes, i);
+                let rest = settings[end..].trim_start();
+                let is_key = depth == 1 && rest.starts_with(':');
+                if is_key
+                    && serde_json::from_str::<String>(&settings[i..end])
+                        .is_ok_and(|key| key == "snippets")
+                {
+                    let value = settings.len() - rest[1..].trim_start().len();
+                    return (bytes[value] == b'"').then(|| value..string_end(bytes, value));
+                }
+                i = end;
+                continue;
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Returns the index right after the closing quote of the JSON string starting at `start`.
+fn string_end(bytes: &[u8], start: usize) -> usize {
+    let mut i = start + 1;
+    while bytes[i] != b'"' {
+        if bytes[i] == b'\\' {
+            i += 1;
+        }
+        i += 1;
+    }
+    i + 1
+}
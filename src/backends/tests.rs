@@ -0,0 +1,133 @@
+//! Throws generated input at the deserializers, which should never panic and whose output
+//! should survive a roundtrip unchanged
+
+use proptest::prelude::*;
+
+use super::*;
+use crate::{
+    regex::{self, Dialect},
+    trigger::RegexFlags,
+};
+
+/// Mostly well-formed snippets with arbitrary bodies, so most inputs get past the header.
+fn ultisnips_snippet() -> impl Strategy<Value = String> {
+    let directive = prop_oneof![
+        "priority -?[0-9]{1,2}",
+        "(context|pre_expand|post_jump) \"[ -~]{0,8}\"",
+        "(extends|clearsnippets)( [a-z]{1,4}){0,2}",
+        "#[ -~]{0,12}",
+        Just(String::new()),
+    ];
+    let header = "snippet ([!-~]{1,6}|\"[ -~]{1,6}\")( \"[ -~]{0,8}\"( [a-zA-Z]{0,4})?)?";
+    let body = prop::collection::vec(prop_oneof!["[ -~]{0,24}", "\\PC{0,12}"], 0..4);
+
+    (prop::collection::vec(directive, 0..3), header, body).prop_map(|(directives, header, body)| {
+        let mut lines = directives;
+        lines.push(header);
+        lines.extend(body);
+        lines.push("endsnippet".to_string());
+        lines.join("\n")
+    })
+}
+
+fn ultisnips_file() -> impl Strategy<Value = String> {
+    let part = prop_oneof![
+        4 => ultisnips_snippet(),
+        1 => "global !p\n[ -~]{0,12}\nendglobal",
+        1 => "[ -~\n]{0,32}",
+    ];
+    prop::collection::vec(part, 0..6).prop_map(|parts| parts.join("\n\n"))
+}
+
+/// A JSON string with whatever in it, or sometimes something that isn't even a string.
+fn ols_value(chars: &'static str) -> impl Strategy<Value = String> {
+    prop_oneof![
+        4 => chars.prop_map(|text| serde_json::to_string(&text).unwrap()),
+        1 => "\\PC{0,8}".prop_map(|text| serde_json::to_string(&text).unwrap()),
+        1 => "[ -~]{0,12}",
+    ]
+}
+
+fn ols_entry() -> impl Strategy<Value = String> {
+    let options = proptest::option::of(ols_value("[rAvwmMntcq]{0,4}"));
+    (ols_value("[ -~]{1,12}"), ols_value("[ -~]{0,24}"), options).prop_map(
+        |(trigger, replacement, options)| {
+            let options = options.map_or(String::new(), |options| format!(", options: {options}"));
+            format!("{{trigger: {trigger}, replacement: {replacement}{options}}}")
+        },
+    )
+}
+
+fn ols_file() -> impl Strategy<Value = String> {
+    let separator = prop_oneof![Just(",\n"), Just(", // note\n"), Just(",\n\n/* a\nb */\n")];
+    prop_oneof![
+        4 => prop::collection::vec((ols_entry(), separator), 0..6).prop_map(|entries| {
+            let entries: String = entries
+                .into_iter()
+                .map(|(entry, separator)| entry + separator)
+                .collect();
+            format!("// snippets\n[\n{entries}]")
+        }),
+        1 => "[ -~\n]{0,64}",
+    ]
+}
+
+/// If `input` deserializes at all, serializing it and reading that back in needs to work, and
+/// serializing again needs to give the same output.
+fn assert_stable_roundtrip(backend: &dyn Backend, input: &str) -> Result<(), TestCaseError> {
+    let Ok(ir) = backend.deserialize(input) else {
+        return Ok(());
+    };
+    let Ok(first) = backend.serialize(&ir) else {
+        // some things just can't be written, like a trigger containing every quote
+        return Ok(());
+    };
+
+    let reread = backend.deserialize(&first);
+    prop_assert!(
+        reread.is_ok(),
+        "couldn't read own output {first:?}: {:?}",
+        reread.err()
+    );
+    let second = backend.serialize(&reread.unwrap());
+    prop_assert_eq!(second.ok(), Some(first));
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn regex_translation_never_panics(
+        pattern in "([a-zA-Z0-9(){}<>=!?:*+|^$#,\\\\\\[\\]-]|\\PC){0,24}",
+        unicode: bool,
+    ) {
+        for (from, to) in [
+            (Dialect::JavaScript, Dialect::Python),
+            (Dialect::Python, Dialect::JavaScript),
+        ] {
+            let flags = RegexFlags {
+                unicode,
+                dialect: Some(from),
+                ..Default::default()
+            };
+            let _ = regex::translate(&pattern, flags, to);
+        }
+    }
+
+    #[test]
+    fn ultisnips_roundtrips(input in ultisnips_file()) {
+        assert_stable_roundtrip(&UltiSnips::default(), &input)?;
+    }
+
+    #[test]
+    fn ols_roundtrips(input in ols_file()) {
+        assert_stable_roundtrip(&Ols, &input)?;
+    }
+
+    #[test]
+    fn ir_keeps_everything(input in ultisnips_file()) {
+        if let Ok(ir) = UltiSnips::default().deserialize(&input) {
+            let dumped = Ir.serialize(&ir).unwrap();
+            prop_assert_eq!(Ir.deserialize(&dumped).unwrap(), ir);
+        }
+    }
+}
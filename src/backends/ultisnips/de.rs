@@ -1,54 +1,225 @@
-use std::num::ParseIntError;
+use std::{collections::BTreeMap, mem, num::ParseIntError};
 
 use anyhow::Result;
 use itertools::Itertools;
 use thiserror::Error;
 use unicode_segmentation::UnicodeSegmentation;
 
-use crate::{Snippet, SnippetFile};
+use crate::{
+    action::Action,
+    body::{Body, BodyBuilder, Language, Node, Transform},
+    context::Context,
+    options::{take_letter, Options},
+    regex::Dialect,
+    span::{LineIndex, Span, SpannedError},
+    trigger::{RegexFlags, TriggerKind},
+    trivia::{self, Trivia},
+    variable::Variable,
+    Clear, Global, Snippet, SnippetFile,
+};
+
+use super::{events, variables, ModeChecks, NAME, OPTIONS};
 
 pub fn deserialize(input: &str) -> Result<SnippetFile> {
-    // TODO: `extends` command, maybe not even necessary
-
+    let index = LineIndex::new(input);
     let mut snippets = Vec::new();
+    let mut extends = Vec::new();
+    let mut globals = Vec::new();
+    let mut clears = Vec::new();
     let mut current_priority = None;
+    let mut pending_context = None;
+    let mut pending_actions = Vec::new();
+    let mut pending_trivia = Vec::new();
+    // split off whatever comes first, be it a snippet or a global
+    let mut header = None;
+    // snippets are usually separated by one blank line, which the serializer writes anyway
+    let mut after_snippet = false;
 
     // external since it's also used inside the loop itself
     let mut lines_iter = input.lines().peekable();
 
     while let Some(line) = lines_iter.next() {
         let line = line.trim();
+        let is_separator = std::mem::take(&mut after_snippet);
 
-        if line.starts_with('#') {
+        if let Some(comment) = line.strip_prefix('#') {
+            pending_trivia.push(trivia::comment(comment));
             continue;
         }
 
         match line.split_whitespace().next() {
-            None => continue, // will have just been whitespace or completely empty
+            None => {
+                // will have just been whitespace or completely empty
+                let at_start = snippets.is_empty() && pending_trivia.is_empty();
+                if !is_separator && !at_start {
+                    pending_trivia.push(Trivia::Blank);
+                }
+            }
             Some("snippet") => {
-                let mut relevant_lines = vec![line.to_string()];
-
-                relevant_lines.extend(
-                    lines_iter
-                        .peeking_take_while(|line| line.trim() != "endsnippet")
-                        .map(|line| line.to_string()),
-                );
-                relevant_lines.push(lines_iter.next().unwrap().to_string());
-
-                let snippet = parse_snippet(&relevant_lines, current_priority)?;
+                let mut relevant_lines = vec![line];
+
+                relevant_lines
+                    .extend(lines_iter.peeking_take_while(|line| line.trim() != "endsnippet"));
+                let end = lines_iter
+                    .next()
+                    .ok_or_else(|| {
+                        SpannedError::new(index.span_of(line), ParseError::UnterminatedSnippet)
+                    })?
+                    .trim();
+                relevant_lines.push(end);
+
+                let mut snippet =
+                    parse_snippet(&relevant_lines, current_priority, pending_context.take())
+                        .map_err(|err| SpannedError::new(index.span_of(line), err))?;
+                snippet.leading = mem::take(&mut pending_trivia);
+                header.get_or_insert_with(|| split_header(&mut snippet.leading));
+                snippet.actions = mem::take(&mut pending_actions);
+                snippet.span = index.span_of(line).to(&index.span_of(end));
                 snippets.push(snippet);
+                after_snippet = true;
+            }
+            Some("extends") => {
+                // `extends tex, markdown`, can be given multiple times
+                let filetypes = line["extends".len()..].split(',').map(str::trim);
+                extends.extend(
+                    filetypes
+                        .filter(|filetype| !filetype.is_empty())
+                        .map(str::to_string),
+                );
+                // just like after a snippet, the blank line after it is implied
+                after_snippet = true;
+            }
+            Some("global") => {
+                let (mut global, end) = parse_global(line, &mut lines_iter)
+                    .map_err(|err| SpannedError::new(index.span_of(line), err))?;
+                global.leading = mem::take(&mut pending_trivia);
+                // globals are written first, with nothing before them to be separated from
+                let blanks = global
+                    .leading
+                    .iter()
+                    .take_while(|line| **line == Trivia::Blank);
+                global.leading.drain(..blanks.count());
+                header.get_or_insert_with(|| split_header(&mut global.leading));
+                global.span = index.span_of(line).to(&index.span_of(end));
+                globals.push(global);
+                after_snippet = true;
+            }
+            Some("priority") => {
+                current_priority = Some(
+                    parse_priority(line)
+                        .map_err(|err| SpannedError::new(index.span_of(line), err))?,
+                )
+            }
+            Some("context") => {
+                pending_context = Some(
+                    parse_expression(line, "context")
+                        .map_err(|err| SpannedError::new(index.span_of(line), err))?,
+                )
+            }
+            Some(directive @ ("pre_expand" | "post_expand" | "post_jump")) => {
+                let source = parse_expression(line, directive)
+                    .map_err(|err| SpannedError::new(index.span_of(line), err))?;
+                let (event, _) = events()
+                    .into_iter()
+                    .find(|(_, name)| *name == directive)
+                    .expect("matched only known directives");
+                pending_actions.push(Action {
+                    event,
+                    language: Language::Python,
+                    source,
+                });
+            }
+            Some("clearsnippets") => {
+                clears.push(Clear {
+                    triggers: line
+                        .split_whitespace()
+                        .skip(1)
+                        .map(str::to_string)
+                        .collect(),
+                    priority: current_priority,
+                });
+                after_snippet = true;
             }
-            Some("priority") => current_priority = Some(parse_priority(line)?),
             Some(unknown) => {
-                return Err(ParseError::UnknownDirective {
+                let err = ParseError::UnknownDirective {
                     directive: unknown.to_string(),
-                }
-                .into())
+                };
+                return Err(SpannedError::new(index.span_of(unknown), err).into());
             }
         }
     }
 
-    Ok(SnippetFile { snippets })
+    while pending_trivia.last() == Some(&Trivia::Blank) {
+        pending_trivia.pop();
+    }
+
+    let (header, footer) = match header {
+        None => (pending_trivia, Vec::new()),
+        Some(header) => (header, pending_trivia),
+    };
+
+    Ok(SnippetFile {
+        header,
+        snippets,
+        footer,
+        extends,
+        globals,
+        clears,
+        ..Default::default()
+    })
+}
+
+/// Comments directly above the first snippet document it, but if there's a blank line between
+/// them, everything above the last blank line is more likely about the whole file.
+fn split_header(leading: &mut Vec<Trivia>) -> Vec<Trivia> {
+    let Some(last_blank) = leading.iter().rposition(|line| *line == Trivia::Blank) else {
+        return Vec::new();
+    };
+    if !leading[..last_blank]
+        .iter()
+        .any(|line| matches!(line, Trivia::Comment(_)))
+    {
+        return Vec::new();
+    }
+
+    let mut header: Vec<_> = leading.drain(..=last_blank).collect();
+    // the blank line itself, the serializer separates the header anyway
+    header.pop();
+    header
+}
+
+/// Parses a `global !p` block, taking its lines up to and including `endglobal`, which is
+/// returned along with it.
+fn parse_global<'a>(
+    line: &str,
+    lines: &mut impl Iterator<Item = &'a str>,
+) -> Result<(Global, &'a str), ParseError> {
+    let language = match line["global".len()..].trim() {
+        "!p" => Language::Python,
+        "!v" => Language::Vimscript,
+        other => {
+            return Err(ParseError::UnknownGlobalLanguage {
+                language: other.to_string(),
+            })
+        }
+    };
+
+    let mut source = Vec::new();
+    let end = loop {
+        match lines.next() {
+            Some(line) if line.trim() == "endglobal" => break line.trim(),
+            Some(line) => source.push(line),
+            None => return Err(ParseError::UnterminatedGlobal),
+        }
+    };
+
+    let global = Global {
+        language,
+        source: source.join("\n"),
+        leading: Vec::new(),
+        span: Span::default(),
+    };
+    Ok((global, end))
 }
 
 #[derive(Debug, Error)]
@@ -57,43 +228,59 @@ enum ParseError {
     UnknownDirective { directive: String },
     #[error("found no trigger after `snippet`")]
     MissingSnippetTrigger,
+    #[error("`snippet` is never closed by `endsnippet`")]
+    UnterminatedSnippet,
+    #[error("expected the trigger `{trigger}` to be quoted")]
+    UnquotedTrigger { trigger: String },
     #[error("description consists of only one unmatched quote")]
     UnmatchedDescQuote,
     #[error("found no number after `priority` directive")]
     MissingPriorityNumber,
     #[error("tried to parse number in `{subject}` but failed: {err}")]
     ParsePriorityNumber { subject: String, err: ParseIntError },
+    #[error("expected the expression in `{subject}` to be enclosed in double quotes")]
+    UnquotedExpression { subject: String },
+    #[error("`global` blocks can only be `!p` or `!v`, not `{language}`")]
+    UnknownGlobalLanguage { language: String },
+    #[error("`global` block is never closed by `endglobal`")]
+    UnterminatedGlobal,
 }
 
-fn parse_snippet(lines: &[String], priority: Option<i64>) -> Result<Snippet, ParseError> {
+fn parse_snippet(
+    lines: &[&str],
+    priority: Option<i64>,
+    context: Option<String>,
+) -> Result<Snippet, ParseError> {
     // basically snippet/source/file/ulti_snips.py in the UltiSnips repo ported
-    let first_line = lines.get(0).expect("caller passing lines to parse");
-    let signature = extract_signature(first_line)?;
+    let first_line = lines.first().expect("caller passing lines to parse");
+    let signature = extract_signature(first_line, context)?;
 
-    let replacement = lines[1..lines.len() - 1].iter().format("\n").to_string();
+    let body = parse_body(&lines[1..lines.len() - 1].iter().format("\n").to_string());
 
     Ok(Snippet {
-        replacement,
+        body,
         priority,
         ..signature
     })
 }
 
-fn extract_signature(line: &str) -> Result<Snippet, ParseError> {
-    // the subject to parse is `snippet <trigger> [ "<description>" [ <options> ] ]`
-    // remember: description and options are optional, trigger may be quoted weirdly
+/// Parses the `snippet` line. `context` is the expression of a preceding `context` line, if
+/// there was one.
+fn extract_signature(line: &str, mut context: Option<String>) -> Result<Snippet, ParseError> {
+    // the subject to parse is `snippet <trigger> [ "<description>" [ "<context>" ] [ <options> ] ]`
+    // remember: description, context and options are optional, trigger may be quoted weirdly
     let mut parts: Vec<_> = line.split_whitespace().collect();
 
-    let trigger;
+    let mut trigger;
+    let mut trigger_kind = TriggerKind::Literal;
     let mut description = None;
-    let mut options = None;
+    let mut options = Options::default();
 
     match parts.len() {
-        0 => panic!("expected caller to not pass empty lines"),
-        1 => return Err(ParseError::MissingSnippetTrigger),
+        0 | 1 => return Err(ParseError::MissingSnippetTrigger),
         2 => {
             // `snippet trigger` (being unquoted)
-            trigger = parts.last().unwrap().to_string();
+            trigger = parts[1].to_string();
         }
         _ => {
             // possibly description,
@@ -101,66 +288,109 @@ fn extract_signature(line: &str) -> Result<Snippet, ParseError> {
             // possibly just quoted trigger
 
             // are options there?
-            options = maybe_parse_options(&mut parts);
+            let mut letters = maybe_parse_options(&mut parts).unwrap_or_default();
+
+            // is a context expression there? it looks just like a description, but only exists
+            // with the `e` option if there's no `context` line
+            if context.is_none() && letters.contains('e') {
+                context = maybe_parse_description(&mut parts)?;
+            }
+            if context.is_some() {
+                take_letter(&mut letters, 'e');
+            }
 
             // is a description there?
             description = maybe_parse_description(&mut parts)?;
 
             // then everything remaining will be the trigger
-            trigger = parse_trigger(&mut parts, &options);
+            trigger_kind = parse_trigger_kind(&mut letters);
+            trigger = parse_trigger(&mut parts, trigger_kind.is_regex())?;
+            options = OPTIONS.parse(&letters);
+
+            if let TriggerKind::Regex(flags) = &mut trigger_kind {
+                *flags = RegexFlags {
+                    dialect: Some(Dialect::Python),
+                    ..take_inline_flags(&mut trigger)
+                };
+            }
         }
     }
 
+    let contexts = context
+        .map(|source| Context::Expression {
+            backend: NAME.to_string(),
+            source,
+        })
+        .into_iter()
+        .collect();
+
     Ok(Snippet {
         trigger,
-        replacement: String::new(),
+        trigger_kind,
+        body: Body::default(),
         options,
+        contexts,
+        actions: Vec::new(),
         description,
         priority: None,
+        scope: None,
+        leading: Vec::new(),
+        trailing: Vec::new(),
+        extra: BTreeMap::new(),
+        span: Span::default(),
     })
 }
 
 fn maybe_parse_options(parts: &mut Vec<&str>) -> Option<String> {
-    if !parts.last().unwrap().ends_with('"') && parts[parts.len() - 2].ends_with('"') {
-        parts.pop().map(|opts| opts.to_string())
-    } else {
-        None
+    match parts.as_slice() {
+        // the first part is `snippet` itself, and UltiSnips only looks for options if there are
+        // at least three words after it
+        [_, _, .., before, last] if before.ends_with('"') && !last.contains('"') => {
+            parts.pop().map(|opts| opts.to_string())
+        }
+        _ => None,
     }
 }
 
 fn maybe_parse_description(parts: &mut Vec<&str>) -> Result<Option<String>, ParseError> {
-    if !parts.last().unwrap().ends_with('"') {
+    if !parts.last().is_some_and(|last| last.ends_with('"')) {
         return Ok(None);
     }
 
-    // maybe need to rev again at the end
-    let parts_of_desc = parts
+    // the description starts at the last part starting with a quote, if that's not where the
+    // trigger is already, in which case it's just a quoted trigger
+    let Some(start) = parts
         .iter()
-        .rev()
-        .take_while_inclusive(|part| !part.starts_with('"'))
-        .collect::<Vec<_>>();
-    let removed_part_count = parts_of_desc.len();
+        .rposition(|part| part.starts_with('"'))
+        .filter(|start| *start >= 2)
+    else {
+        return Ok(None);
+    };
 
-    if parts_of_desc.len() == 1 && parts_of_desc[0].len() == 1 {
+    if start == parts.len() - 1 && parts[start] == "\"" {
         return Err(ParseError::UnmatchedDescQuote);
     }
 
-    let quoted_desc = parts_of_desc.into_iter().rev().format(" ").to_string();
-
-    for _ in 0..removed_part_count {
-        parts.pop();
-    }
+    let quoted_desc = parts.drain(start..).format(" ").to_string();
 
     // no need for grapheme magic, the only allowed quote character is "
     Ok(Some(quoted_desc[1..quoted_desc.len() - 1].to_string()))
 }
 
-fn parse_trigger(parts: &mut Vec<&str>, options: &Option<String>) -> String {
-    let is_regex = options
-        .as_ref()
-        .map(|opts| opts.contains('r'))
-        .unwrap_or(false);
+/// Takes the letters deciding the trigger kind out of the option letters.
+fn parse_trigger_kind(letters: &mut String) -> TriggerKind {
+    if take_letter(letters, 'r') {
+        TriggerKind::Regex(RegexFlags::default())
+    } else if take_letter(letters, 'i') {
+        TriggerKind::InWord
+    } else if take_letter(letters, 'w') {
+        TriggerKind::Word
+    } else {
+        TriggerKind::Literal
+    }
+}
 
+fn parse_trigger(parts: &mut Vec<&str>, is_regex: bool) -> Result<String, ParseError> {
     if parts.len() >= 3 || is_regex {
         // quoted
         // actually according to :h UltiSnips-snippet-options, both single-word and
@@ -168,13 +398,113 @@ fn parse_trigger(parts: &mut Vec<&str>, options: &Option<String>) -> String {
         // (and checks for regex, too). so that's emulated here
         let quoted = parts[1..].iter().format(" ").to_string();
         let graphemes: Vec<_> = quoted.graphemes(true).collect();
-        graphemes[1..graphemes.len() - 1].iter().copied().collect()
+        if graphemes.len() < 2 {
+            return Err(ParseError::UnquotedTrigger { trigger: quoted });
+        }
+        Ok(graphemes[1..graphemes.len() - 1].iter().copied().collect())
     } else {
         // unquoted
-        parts.pop().unwrap().to_string()
+        parts
+            .pop()
+            .map(str::to_string)
+            .ok_or(ParseError::MissingSnippetTrigger)
+    }
+}
+
+/// Python has no separate place for regex flags, so they're written as inline group at the
+/// start of the pattern, like `(?i)`. If there is one, it's removed and returned.
+fn take_inline_flags(pattern: &mut String) -> RegexFlags {
+    let Some((letters, _)) = pattern
+        .strip_prefix("(?")
+        .and_then(|rest| rest.split_once(')'))
+    else {
+        return RegexFlags::default();
+    };
+
+    match RegexFlags::from_letters(letters) {
+        Ok(flags) if !letters.is_empty() => {
+            pattern.drain(.."(?)".len() + letters.len());
+            flags
+        }
+        // not flags, but a group of some other kind, or flags Python has but JS doesn't
+        _ => RegexFlags::default(),
+    }
+}
+
+/// Turns `context` expressions checking for modes, like the serializer writes them, back into
+/// the modes. Both the checks in `modes` and the default ones are recognized, as well as calls
+/// to helpers a `global` block defines as one of them.
+pub fn recognize_modes(snippets: &mut SnippetFile, modes: &ModeChecks) {
+    let defaults = ModeChecks::default();
+    let checks = [
+        ("math", Context::Math, [&modes.math, &defaults.math]),
+        ("text", Context::Text, [&modes.text, &defaults.text]),
+        (
+            "code",
+            Context::Code { language: None },
+            [&modes.code, &defaults.code],
+        ),
+    ];
+
+    let mut known = Vec::new();
+    for (name, context, checks) in &checks {
+        for check in checks {
+            known.push((check.to_string(), context));
+            let helper = format!("def {name}():\n\treturn {check}");
+            if (snippets.globals.iter()).any(|global| global.source.contains(&helper)) {
+                known.push((format!("{name}()"), context));
+            }
+        }
+    }
+
+    for snippet in snippets {
+        if let [Context::Expression { backend, source }] = snippet.contexts.as_slice() {
+            if backend == NAME {
+                if let Some(contexts) = modes_of(source, &known) {
+                    snippet.contexts = contexts;
+                }
+            }
+        }
+    }
+}
+
+/// Splits `source` into the known checks it consists of, a single one or multiple ones in
+/// parentheses joined by `or`. [`None`] if there's anything else in there.
+fn modes_of(source: &str, known: &[(String, &Context)]) -> Option<Vec<Context>> {
+    if let Some((_, context)) = known.iter().find(|(check, _)| *check == source) {
+        return Some(vec![(*context).clone()]);
+    }
+
+    let mut contexts = Vec::new();
+    let mut rest = source;
+    loop {
+        let (context, after) = known.iter().find_map(|(check, context)| {
+            let after = rest
+                .strip_prefix('(')?
+                .strip_prefix(check)?
+                .strip_prefix(')')?;
+            Some((context, after))
+        })?;
+        contexts.push((*context).clone());
+        if after.is_empty() {
+            return Some(contexts);
+        }
+        rest = after.strip_prefix(" or ")?;
     }
 }
 
+/// Parses a line like `context "expr"`, returning the expression.
+fn parse_expression(line: &str, directive: &str) -> Result<String, ParseError> {
+    line[directive.len()..]
+        .trim()
+        .strip_prefix('"')
+        .and_then(|rest| rest.strip_suffix('"'))
+        .map(str::to_string)
+        .ok_or_else(|| ParseError::UnquotedExpression {
+            subject: line.to_string(),
+        })
+}
+
 fn parse_priority(line: &str) -> Result<i64, ParseError> {
     line.split_whitespace()
         .nth(1)
@@ -185,3 +515,281 @@ fn parse_priority(line: &str) -> Result<i64, ParseError> {
             err,
         })
 }
+
+/// Characters that can be escaped by a backslash in snippet bodies. Everywhere else, a
+/// backslash is just a backslash.
+pub(super) const ESCAPABLE: &str = "{}\\$`";
+
+fn parse_body(input: &str) -> Body {
+    // see lexer.py in the UltiSnips repo for the original tokenizer
+    let chars: Vec<_> = input.chars().collect();
+    let mut body = parse_nodes(&chars, &mut 0, false);
+    body.resolve_mirrors();
+    body
+}
+
+/// Parses nodes starting at `pos` until the end of input, or if `nested`, until and including
+/// the first unescaped `}`.
+fn parse_nodes(chars: &[char], pos: &mut usize, nested: bool) -> Body {
+    let mut builder = BodyBuilder::default();
+
+    while let Some(&ch) = chars.get(*pos) {
+        match ch {
+            '\\' if chars
+                .get(*pos + 1)
+                .is_some_and(|next| ESCAPABLE.contains(*next)) =>
+            {
+                builder.push_char(chars[*pos + 1]);
+                *pos += 2;
+            }
+            '}' if nested => {
+                *pos += 1;
+                break;
+            }
+            '$' => match parse_dollar(chars, pos) {
+                Some(node) => builder.push(node),
+                None => {
+                    builder.push_char(ch);
+                    *pos += 1;
+                }
+            },
+            '`' => match find_unescaped(chars, *pos + 1, '`') {
+                Some(end) => {
+                    let code: String = chars[*pos + 1..end].iter().collect();
+                    builder.push(parse_interpolation(&code));
+                    *pos = end + 1;
+                }
+                None => {
+                    builder.push_char(ch);
+                    *pos += 1;
+                }
+            },
+            _ => {
+                builder.push_char(ch);
+                *pos += 1;
+            }
+        }
+    }
+
+    builder.finish()
+}
+
+/// Parses the code between two backticks. Code that only inserts a well-known value, like
+/// `` `!v expand('%:t')` ``, or a capture group, like `` `!p snip.rv = match.group(1)` ``, is
+/// recognised as such.
+fn parse_interpolation(code: &str) -> Node {
+    let (language, source) = if let Some(source) = code.strip_prefix("!p") {
+        (Language::Python, source)
+    } else if let Some(source) = code.strip_prefix("!v") {
+        (Language::Vimscript, source)
+    } else {
+        (Language::Shell, code)
+    };
+    // the space separating the prefix isn't part of the code
+    let source = match language {
+        Language::Shell => source,
+        _ => source.strip_prefix(' ').unwrap_or(source),
+    };
+
+    match language {
+        Language::Vimscript => {
+            // `"` and `'` only differ in escapes, which none of the known spellings have
+            let vimscript = source.trim().replace('"', "'");
+            let variable = variables()
+                .into_iter()
+                .find_map(|(variable, spelling)| (spelling == vimscript).then_some(variable));
+            if let Some(variable) = variable {
+                return Node::Variable(variable);
+            }
+        }
+        Language::Python => {
+            if let Some(node) = python_return_value(source).and_then(parse_python_value) {
+                return node;
+            }
+        }
+        _ => (),
+    }
+
+    Node::Code {
+        language,
+        source: source.to_string(),
+    }
+}
+
+/// Returns `value` if `python` is just `snip.rv = value`.
+fn python_return_value(python: &str) -> Option<&str> {
+    let value = python
+        .trim()
+        .strip_prefix("snip.rv")?
+        .trim_start()
+        .strip_prefix('=')?;
+    Some(value.trim())
+}
+
+/// Recognises the Python expressions for the file name and capture groups.
+fn parse_python_value(value: &str) -> Option<Node> {
+    match value {
+        "snip.fn" => return Some(Node::Variable(Variable::FileName)),
+        "snip.basename" => return Some(Node::Variable(Variable::FileStem)),
+        "match.group()" => return Some(Node::Capture(0)),
+        _ => (),
+    }
+
+    let group = value.strip_prefix("match.group(")?.strip_suffix(')')?;
+    Some(Node::Capture(group.trim().parse().ok()?))
+}
+
+/// Tries to parse whatever starts with the `$` at `pos`. Leaves `pos` untouched if it's not
+/// anything special, in which case the `$` is just text.
+fn parse_dollar(chars: &[char], pos: &mut usize) -> Option<Node> {
+    let start = *pos;
+    let mut cursor = start + 1;
+
+    if chars.get(cursor) != Some(&'{') {
+        // `$1`
+        let index = parse_index(chars, &mut cursor)?;
+        *pos = cursor;
+        return Some(tabstop(index));
+    }
+    cursor += 1;
+
+    if chars[cursor..].starts_with(&['V', 'I', 'S', 'U', 'A', 'L']) {
+        cursor += "VISUAL".len();
+        let node = parse_visual(chars, &mut cursor)?;
+        *pos = cursor;
+        return Some(node);
+    }
+
+    let index = parse_index(chars, &mut cursor)?;
+    match chars.get(cursor) {
+        Some('}') => {
+            *pos = cursor + 1;
+            Some(tabstop(index))
+        }
+        Some(':') => {
+            cursor += 1;
+            let default = parse_nodes(chars, &mut cursor, true);
+            *pos = cursor;
+            Some(Node::Placeholder { index, default })
+        }
+        Some('/') => {
+            cursor += 1;
+            let transform = parse_transform(chars, &mut cursor)?;
+            *pos = cursor + 1;
+            Some(Node::Transformation { index, transform })
+        }
+        Some('|') => {
+            cursor += 1;
+            let options = parse_options(chars, &mut cursor)?;
+            *pos = cursor;
+            Some(Node::Choice { index, options })
+        }
+        _ => None,
+    }
+}
+
+/// Parses the options of `${1|a,b|}` after the first `|`, including the closing `|}`.
+fn parse_options(chars: &[char], cursor: &mut usize) -> Option<Vec<String>> {
+    let mut options = Vec::new();
+    loop {
+        let end = find_unescaped_any(chars, *cursor, &[',', '|'])?;
+        options.push(unescape_all(&chars[*cursor..end]));
+        *cursor = end + 1;
+        if chars[end] == '|' {
+            break;
+        }
+    }
+
+    (chars.get(*cursor) == Some(&'}')).then(|| {
+        *cursor += 1;
+        options
+    })
+}
+
+/// Parses the rest of `${VISUAL}`, `${VISUAL:default}` or `${VISUAL:default/regex/format/flags}`
+/// after the `VISUAL`, including the closing brace.
+fn parse_visual(chars: &[char], cursor: &mut usize) -> Option<Node> {
+    let mut default = None;
+    if chars.get(*cursor) == Some(&':') {
+        *cursor += 1;
+        let end = find_unescaped_any(chars, *cursor, &['/', '}'])?;
+        default = Some(unescape_all(&chars[*cursor..end]));
+        *cursor = end;
+    }
+
+    let transform = match chars.get(*cursor) {
+        Some('}') => None,
+        Some('/') => {
+            *cursor += 1;
+            Some(parse_transform(chars, cursor)?)
+        }
+        _ => return None,
+    };
+    *cursor += 1;
+
+    Some(Node::Visual { default, transform })
+}
+
+/// Parses `regex/format/flags` up to the closing brace, which isn't consumed. Escapes are
+/// kept as-is, since the regex and format string need them.
+fn parse_transform(chars: &[char], cursor: &mut usize) -> Option<Transform> {
+    let mut part = |until| {
+        let end = find_unescaped(chars, *cursor, until)?;
+        let part = chars[*cursor..end].iter().collect();
+        *cursor = end + usize::from(until == '/');
+        Some(part)
+    };
+
+    Some(Transform {
+        regex: part('/')?,
+        format: part('/')?,
+        flags: part('}')?,
+    })
+}
+
+/// Removes the backslash in front of any character, like UltiSnips does for defaults of
+/// `${VISUAL}`.
+fn unescape_all(chars: &[char]) -> String {
+    let mut text = String::new();
+    let mut chars = chars.iter();
+    while let Some(&ch) = chars.next() {
+        match ch {
+            '\\' => text.extend(chars.next()),
+            _ => text.push(ch),
+        }
+    }
+    text
+}
+
+fn parse_index(chars: &[char], cursor: &mut usize) -> Option<u32> {
+    let digits: String = chars[*cursor..]
+        .iter()
+        .take_while(|ch| ch.is_ascii_digit())
+        .collect();
+    let index = digits.parse().ok()?;
+    *cursor += digits.len();
+    Some(index)
+}
+
+fn tabstop(index: u32) -> Node {
+    if index == 0 {
+        Node::Final
+    } else {
+        Node::Tabstop(index)
+    }
+}
+
+fn find_unescaped(chars: &[char], cursor: usize, needle: char) -> Option<usize> {
+    find_unescaped_any(chars, cursor, &[needle])
+}
+
+fn find_unescaped_any(chars: &[char], mut cursor: usize, needles: &[char]) -> Option<usize> {
+    while let Some(&ch) = chars.get(cursor) {
+        match ch {
+            '\\' => cursor += 2,
+            _ if needles.contains(&ch) => return Some(cursor),
+            _ => cursor += 1,
+        }
+    }
+    None
+}
@@ -4,28 +4,338 @@ mod tests;
 mod de;
 mod ser;
 
+use std::{
+    collections::{HashSet, VecDeque},
+    fs, mem,
+    path::{Path, PathBuf},
+};
+
 use anyhow::Context;
+use clap::{Arg, ArgAction, ArgMatches};
 
-use crate::SnippetFile;
+use crate::{
+    action::Event,
+    body::Language,
+    diagnostic::Warning,
+    options::{Flag, OptionTable, Support},
+    variable::Variable,
+    SnippetFile,
+};
 
-use super::Backend;
+use super::{write_serialized, Backend};
 
 /// Backend for de- and serializing [UltiSnips] snippet files.
 ///
 /// [UltiSnips]: https://github.com/SirVer/ultisnips
-#[derive(Debug)]
-pub struct UltiSnips;
+#[derive(Clone, Debug, Default)]
+pub struct UltiSnips {
+    /// How snippets limited to math, text or code find out where the cursor is.
+    pub modes: ModeChecks,
+    /// In which order snippets are written.
+    pub layout: Layout,
+}
+
+/// Python expressions checking whether the cursor is in a mode, written as `context` of
+/// snippets limited to it. Inline and display math are both just math. By default they ask
+/// [VimTeX].
+///
+/// [VimTeX]: https://github.com/lervag/vimtex
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ModeChecks {
+    pub math: String,
+    pub text: String,
+    pub code: String,
+    /// Defines the checks once as `math()`, `text()` and `code()` in a `global !p` block,
+    /// and calls those in the `context` lines instead, so they're easy to change later.
+    pub helpers: bool,
+}
+
+impl Default for ModeChecks {
+    fn default() -> Self {
+        Self {
+            math: "vim.eval('vimtex#syntax#in_mathzone()') == '1'".to_string(),
+            text: "vim.eval('vimtex#syntax#in_mathzone()') == '0'".to_string(),
+            code:
+                r#"'code' in vim.eval('synIDattr(synID(line("."), col("."), 1), "name")').lower()"#
+                    .to_string(),
+            helpers: false,
+        }
+    }
+}
+
+/// How snippets are arranged in the output. By default, they're written in the order they
+/// come in.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Layout {
+    /// Puts snippets with the same key next to each other.
+    pub group_by: Option<GroupBy>,
+    /// Sorts snippets by trigger inside each group, keeping the order of equal ones.
+    pub sort: bool,
+    /// Writes a comment naming each group before it, like `# --- math ---`.
+    pub headers: bool,
+}
+
+/// What [`Layout::group_by`] groups snippets by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GroupBy {
+    /// From lowest to highest, so every `priority` line is only written once.
+    Priority,
+    /// The modes or expression in their `context` line, in order of appearance.
+    Context,
+    /// The part of their description before the first `:`, in order of appearance.
+    Description,
+}
 
 impl Backend for UltiSnips {
     fn name(&self) -> &'static str {
-        "ultisnips"
+        NAME
     }
 
-    fn deserialize(&self, input: &str) -> anyhow::Result<SnippetFile> {
-        de::deserialize(input).context("error while parsing UltiSnips snippets")
+    fn deserialize_with_warnings(
+        &self,
+        input: &str,
+        warnings: &mut Vec<Warning>,
+    ) -> anyhow::Result<SnippetFile> {
+        let mut snippets =
+            de::deserialize(input).context("error while parsing UltiSnips snippets")?;
+        de::recognize_modes(&mut snippets, &self.modes);
+        for snippet in &snippets {
+            OPTIONS.warn_unknown(snippet, warnings);
+        }
+        Ok(snippets)
     }
 
-    fn serialize(&self, snippets: &SnippetFile) -> anyhow::Result<String> {
-        ser::serialize(snippets)
+    fn serialize_with_warnings(
+        &self,
+        snippets: &SnippetFile,
+        warnings: &mut Vec<Warning>,
+    ) -> anyhow::Result<String> {
+        ser::serialize(self, snippets, warnings)
     }
+
+    fn languages(&self) -> &'static [Language] {
+        &[Language::Python, Language::Vimscript, Language::Shell]
+    }
+
+    fn args(&self) -> Vec<Arg> {
+        let check = |mode: &str| {
+            Arg::new(format!("ultisnips-{mode}"))
+                .long(format!("ultisnips-{mode}"))
+                .value_name("EXPR")
+                .help(format!(
+                    "Python expression telling if the cursor is in {mode}, for the `context` of \
+                     {mode}-only snippets. Asks VimTeX by default"
+                ))
+        };
+
+        vec![
+            check("math"),
+            check("text"),
+            check("code"),
+            Arg::new("ultisnips-mode-helpers")
+                .long("ultisnips-mode-helpers")
+                .action(ArgAction::SetTrue)
+                .help("Define the mode checks once as `math()`, `text()` and `code()` in a `global !p` block"),
+            Arg::new("ultisnips-group-by")
+                .long("ultisnips-group-by")
+                .value_parser(["priority", "context", "description"])
+                .help("Put snippets with the same priority, context or description prefix next to each other"),
+            Arg::new("ultisnips-sort")
+                .long("ultisnips-sort")
+                .action(ArgAction::SetTrue)
+                .help("Sort snippets by trigger, inside each group if grouped"),
+            Arg::new("ultisnips-headers")
+                .long("ultisnips-headers")
+                .action(ArgAction::SetTrue)
+                .requires("ultisnips-group-by")
+                .help("Write a comment naming each group before it"),
+        ]
+    }
+
+    fn configure(&mut self, matches: &ArgMatches) {
+        let modes = &mut self.modes;
+        for (mode, check) in [
+            ("math", &mut modes.math),
+            ("text", &mut modes.text),
+            ("code", &mut modes.code),
+        ] {
+            if let Some(expr) = matches.get_one::<String>(&format!("ultisnips-{mode}")) {
+                check.clone_from(expr);
+            }
+        }
+        modes.helpers = matches.get_flag("ultisnips-mode-helpers");
+
+        let layout = &mut self.layout;
+        layout.group_by = (matches.get_one::<String>("ultisnips-group-by")).map(|group_by| {
+            match group_by.as_str() {
+                "priority" => GroupBy::Priority,
+                "context" => GroupBy::Context,
+                _ => GroupBy::Description,
+            }
+        });
+        layout.sort = matches.get_flag("ultisnips-sort");
+        layout.headers = matches.get_flag("ultisnips-headers");
+    }
+
+    /// UltiSnips looks for `<filetype>.snippets` and `<filetype>_<anything>.snippets`, where
+    /// the filetype `all` applies everywhere.
+    fn scope_from_path(&self, path: &Path) -> Option<String> {
+        if path.extension()? != "snippets" {
+            return None;
+        }
+
+        let stem = path.file_stem()?.to_str()?;
+        let filetype = stem.split('_').next()?;
+        (!filetype.is_empty() && filetype != "all").then(|| filetype.to_string())
+    }
+
+    /// If the path is a directory, writes one `<filetype>.snippets` file per scope into it.
+    fn write(
+        &self,
+        path: &Path,
+        snippets: &SnippetFile,
+        warnings: &mut Vec<Warning>,
+    ) -> anyhow::Result<()> {
+        if !path.is_dir() {
+            return write_serialized(self, path, snippets, warnings);
+        }
+
+        for (scope, snippets) in snippets.split_by_scope() {
+            let path = path.join(format!("{}.snippets", scope.as_deref().unwrap_or("all")));
+            write_serialized(self, &path, &snippets, warnings)?;
+        }
+        Ok(())
+    }
+}
+
+impl UltiSnips {
+    /// Inlines the snippets and globals of every filetype `snippets` extends, recursively, and
+    /// clears its `extends`. They're loaded from `dir` like UltiSnips finds them, from
+    /// `<filetype>.snippets`, `<filetype>_*.snippets` and `<filetype>/*.snippets`. Filetypes
+    /// without any such file are skipped, UltiSnips doesn't mind them either.
+    ///
+    /// Inlined snippets keep the priority they had in their own file, so whichever snippet
+    /// UltiSnips would have picked for a trigger still wins, and the `clearsnippets` of
+    /// `snippets` itself are applied to them. They apply to the scope of `snippets`, as that's
+    /// where UltiSnips would offer them.
+    pub fn resolve_extends(&self, snippets: &mut SnippetFile, dir: &Path) -> anyhow::Result<()> {
+        let mut pending: VecDeque<_> = mem::take(snippets.extends_mut()).into();
+        let own = snippets.len();
+        let mut seen: HashSet<_> = snippets.scope().map(str::to_string).into_iter().collect();
+
+        while let Some(filetype) = pending.pop_front() {
+            if !seen.insert(filetype.clone()) {
+                continue;
+            }
+
+            for path in files_for(dir, &filetype)? {
+                let input = fs::read_to_string(&path)
+                    .with_context(|| format!("could not read {}", path.display()))?;
+                let mut extended = self.deserialize(&input).with_context(|| {
+                    format!(
+                        "could not load `{filetype}` snippets from {}",
+                        path.display()
+                    )
+                })?;
+                extended.set_file(&path);
+
+                pending.extend(mem::take(extended.extends_mut()));
+                snippets.globals_mut().append(extended.globals_mut());
+                snippets.extend(extended);
+            }
+        }
+
+        // `clearsnippets` of the file itself removes extended snippets with a lower priority
+        let clears = snippets.clears().to_vec();
+        let mut position = 0;
+        snippets.retain(|snippet| {
+            position += 1;
+            position <= own || !clears.iter().any(|clear| clear.removes(snippet))
+        });
+
+        Ok(())
+    }
+}
+
+/// Lists the files in `dir` UltiSnips would read for `filetype`, sorted by path.
+fn files_for(dir: &Path, filetype: &str) -> anyhow::Result<Vec<PathBuf>> {
+    let is_snippets = |path: &Path| path.extension().is_some_and(|ext| ext == "snippets");
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(dir).with_context(|| format!("could not read {}", dir.display()))? {
+        let path = entry?.path();
+
+        if path.is_dir() {
+            if path.file_name().is_some_and(|name| name == filetype) {
+                for entry in fs::read_dir(&path)? {
+                    let path = entry?.path();
+                    if is_snippets(&path) {
+                        files.push(path);
+                    }
+                }
+            }
+            continue;
+        }
+
+        let Some(stem) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        if is_snippets(&path) && (stem == filetype || stem.starts_with(&format!("{filetype}_"))) {
+            files.push(path);
+        }
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+const NAME: &str = "ultisnips";
+
+/// How UltiSnips spells each flag, see `:h UltiSnips-snippet-options`. `r`, `w` and `i` are
+/// handled by the trigger kind instead, `e` by the context.
+pub static OPTIONS: OptionTable = OptionTable {
+    backend: NAME,
+    entries: &[
+        (Flag::AutoExpand, Support::Letter('A')),
+        (Flag::BeginningOfLine, Support::Letter('b')),
+        (
+            Flag::Visual,
+            Support::Unsupported("every snippet can use the selection through ${VISUAL}"),
+        ),
+        (Flag::KeepTabs, Support::Letter('t')),
+        (Flag::TrimBeforeJump, Support::Letter('s')),
+        (Flag::TrimWhitespace, Support::Letter('m')),
+    ],
+};
+
+/// The directive UltiSnips uses for each event. Actions are always Python.
+fn events() -> [(Event, &'static str); 3] {
+    [
+        (Event::PreExpand, "pre_expand"),
+        (Event::PostExpand, "post_expand"),
+        (Event::PostJump, "post_jump"),
+    ]
+}
+
+/// How each variable is spelled as Vim script, which UltiSnips evaluates in `` `!v ...` ``.
+fn variables() -> [(Variable, &'static str); 17] {
+    [
+        (Variable::FileName, "expand('%:t')"),
+        (Variable::FileStem, "expand('%:t:r')"),
+        (Variable::FilePath, "expand('%:p')"),
+        (Variable::Directory, "expand('%:p:h')"),
+        (Variable::LineNumber, "line('.')"),
+        (Variable::Clipboard, "@+"),
+        (Variable::Year, "strftime('%Y')"),
+        (Variable::YearShort, "strftime('%y')"),
+        (Variable::Month, "strftime('%m')"),
+        (Variable::MonthName, "strftime('%B')"),
+        (Variable::MonthNameShort, "strftime('%b')"),
+        (Variable::Day, "strftime('%d')"),
+        (Variable::DayName, "strftime('%A')"),
+        (Variable::DayNameShort, "strftime('%a')"),
+        (Variable::Hour, "strftime('%H')"),
+        (Variable::Minute, "strftime('%M')"),
+        (Variable::Second, "strftime('%S')"),
+    ]
 }
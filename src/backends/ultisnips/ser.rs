@@ -1,33 +1,211 @@
-use std::{collections::HashSet, fmt::Write};
+use std::{borrow::Cow, collections::HashSet, fmt::Write};
 
+use itertools::Itertools;
 use thiserror::Error;
 
-use crate::SnippetFile;
+use crate::{
+    body::{Body, Language, Node, Transform},
+    context::Context,
+    diagnostic::Warning,
+    options::Options,
+    regex::{self, Dialect, TranslateError},
+    span::Span,
+    trigger::TriggerKind,
+    trivia::{write_trivia, Trivia},
+    Global, Snippet, SnippetFile,
+};
 
-pub fn serialize(snippets: &SnippetFile) -> anyhow::Result<String> {
+use super::{de::ESCAPABLE, events, variables, GroupBy, ModeChecks, UltiSnips, NAME, OPTIONS};
+use crate::backends::{warn_foreign_code, warn_foreign_extra};
+
+pub fn serialize(
+    backend: &UltiSnips,
+    snippets: &SnippetFile,
+    warnings: &mut Vec<Warning>,
+) -> anyhow::Result<String> {
     let mut output = String::new();
     let mut last_priority = 0;
 
-    for snippet in &snippets.snippets {
-        // very much recommended to look at :h UltiSnips-basic-syntax while reading this
-        write_and_update_priority(&mut output, &mut last_priority, snippet.priority);
+    let scopes = snippets.scopes();
+    if scopes.len() > 1 {
+        warnings.push(Warning::for_file(
+            format!(
+                "the snippets for {} ended up in one file",
+                (scopes.iter())
+                    .map(|scope| format!("`{}`", scope.unwrap_or("all")))
+                    .join(", "),
+            ),
+            "UltiSnips only splits them by filetype when writing into a directory",
+        ));
+    }
 
-        write!(output, "snippet").unwrap();
+    write_trivia(&mut output, &snippets.header, "#", "");
+    if !snippets.header.is_empty() {
+        writeln!(output).unwrap();
+    }
 
-        write_trigger(&mut output, &snippet.trigger)?;
-        write_description_and_options(
-            &mut output,
-            snippet.description.as_deref(),
-            snippet.options.as_deref(),
-        );
+    if !snippets.extends.is_empty() {
+        writeln!(output, "extends {}", snippets.extends.join(", ")).unwrap();
+        writeln!(output).unwrap();
+    }
+
+    let (use_helpers, helpers) = mode_helpers(snippets, &backend.modes);
+    let backend = &UltiSnips {
+        modes: ModeChecks {
+            helpers: use_helpers,
+            ..backend.modes.clone()
+        },
+        ..backend.clone()
+    };
+    for global in helpers.iter().chain(&snippets.globals) {
+        write_global(&mut output, global, warnings);
+    }
 
-        writeln!(output, "\n{}", snippet.replacement).unwrap();
-        writeln!(output, "endsnippet\n").unwrap();
+    for clear in &snippets.clears {
+        write_and_update_priority(&mut output, &mut last_priority, clear.priority);
+        let triggers = clear.triggers.iter().map(|trigger| format!(" {trigger}"));
+        writeln!(output, "clearsnippets{}", triggers.format("")).unwrap();
+    }
+    if !snippets.clears.is_empty() {
+        writeln!(output).unwrap();
     }
 
+    let groups = arrange(&snippets.snippets, backend);
+    for (name, group) in &groups {
+        if let Some(name) = name.as_deref().filter(|_| backend.layout.headers) {
+            write_trivia(&mut output, &[section_header(name)], "#", "");
+        }
+        for snippet in group {
+            write_snippet(&mut output, snippet, backend, &mut last_priority, warnings)?;
+        }
+    }
+
+    write_trivia(&mut output, &snippets.footer, "#", "");
+
     Ok(output)
 }
 
+/// Writes a single snippet, followed by a blank line.
+fn write_snippet(
+    output: &mut String,
+    snippet: &Snippet,
+    backend: &UltiSnips,
+    last_priority: &mut i64,
+    warnings: &mut Vec<Warning>,
+) -> anyhow::Result<()> {
+    // very much recommended to look at :h UltiSnips-basic-syntax while reading this
+    // headers read back in from an earlier run are written anew above instead
+    let leading = (snippet.leading.iter())
+        .filter(|line| !(backend.layout.headers && is_section_header(line)));
+    // comments come first, they were above the priority line when read in
+    write_trivia(output, leading, "#", "");
+    write_and_update_priority(output, last_priority, snippet.priority);
+    write_choices(output, snippet, warnings);
+    warn_foreign_code(backend, snippet, warnings);
+    warn_foreign_extra(backend, snippet, warnings);
+    warn_contexts(snippet, warnings);
+    OPTIONS.warn_skipped(snippet, warnings);
+
+    write_actions(output, snippet, warnings);
+
+    let context = context_expression(&snippet.contexts, &backend.modes);
+    if let Some(context) = &context {
+        writeln!(output, "context \"{context}\"").unwrap();
+    }
+
+    write!(output, "snippet").unwrap();
+
+    write_trigger(output, snippet, warnings)?;
+    write_description_and_options(
+        output,
+        snippet.description.as_deref(),
+        &snippet.options,
+        snippet.trigger_kind,
+        context.is_some(),
+    );
+
+    writeln!(output).unwrap();
+    write_body(output, &snippet.body, false);
+    writeln!(output).unwrap();
+    writeln!(output, "endsnippet").unwrap();
+    // UltiSnips has no comments after something on the same line, the next best thing
+    write_trivia(output, &snippet.trailing, "#", "");
+    writeln!(output).unwrap();
+
+    Ok(())
+}
+
+/// Orders the snippets according to the layout of `backend`, in groups named by what they
+/// have in common, if they're grouped at all.
+fn arrange<'a>(
+    snippets: &'a [Snippet],
+    backend: &UltiSnips,
+) -> Vec<(Option<String>, Vec<&'a Snippet>)> {
+    let mut groups: Vec<(Option<String>, Vec<_>)> = Vec::new();
+
+    match backend.layout.group_by {
+        None => groups.push((None, snippets.iter().collect())),
+        Some(group_by) => {
+            for snippet in snippets {
+                let name = group_name(snippet, group_by, &backend.modes);
+                match groups.iter_mut().find(|(other, _)| *other == name) {
+                    Some((_, group)) => group.push(snippet),
+                    None => groups.push((name, vec![snippet])),
+                }
+            }
+
+            if group_by == GroupBy::Priority {
+                groups.sort_by_key(|(_, group)| group[0].priority.unwrap_or(0));
+            }
+        }
+    }
+
+    if backend.layout.sort {
+        for (_, group) in &mut groups {
+            group.sort_by(|a, b| a.trigger.cmp(&b.trigger));
+        }
+    }
+
+    groups
+}
+
+fn group_name(snippet: &Snippet, group_by: GroupBy, modes: &ModeChecks) -> Option<String> {
+    match group_by {
+        GroupBy::Priority => Some(format!("priority {}", snippet.priority.unwrap_or(0))),
+        GroupBy::Context => {
+            let names: Vec<_> = (snippet.contexts.iter())
+                .filter_map(|context| match mode_check(context, modes) {
+                    Some((name, _)) => Some(Cow::Borrowed(name)),
+                    None => mode_expression(context, modes),
+                })
+                .unique()
+                .collect();
+            Some(if names.is_empty() {
+                "everywhere".to_string()
+            } else {
+                names.join(" or ")
+            })
+        }
+        GroupBy::Description => {
+            let (prefix, _) = snippet.description.as_deref()?.split_once(':')?;
+            Some(prefix.trim().to_string()).filter(|prefix| !prefix.is_empty())
+        }
+    }
+}
+
+fn section_header(name: &str) -> Trivia {
+    Trivia::Comment(format!("--- {name} ---"))
+}
+
+/// If `trivia` looks like it came from [`section_header`], whatever the name.
+fn is_section_header(trivia: &Trivia) -> bool {
+    matches!(
+        trivia,
+        Trivia::Comment(comment)
+            if comment.starts_with("--- ") && comment.ends_with(" ---") && !comment.contains('\n')
+    )
+}
+
 fn write_and_update_priority(output: &mut String, last_priority: &mut i64, priority: Option<i64>) {
     let priority = priority.unwrap_or(0);
     if priority == *last_priority {
@@ -38,8 +216,189 @@ fn write_and_update_priority(output: &mut String, last_priority: &mut i64, prior
     writeln!(output, "priority {priority}").unwrap();
 }
 
-fn write_trigger(output: &mut String, trigger: &str) -> Result<(), SerializeError> {
-    if !trigger.contains(' ') {
+/// UltiSnips has no choices, they're written as placeholder with the first option. At least
+/// the alternatives are listed in a comment, so they aren't lost completely.
+fn write_choices(output: &mut String, snippet: &Snippet, warnings: &mut Vec<Warning>) {
+    for (index, options) in snippet.body.choices() {
+        let comment = format!("${index} is one of: {}", options.join(", "));
+        write_trivia(output, &[Trivia::Comment(comment)], "#", "");
+        warnings.push(Warning::new(
+            snippet,
+            format!("${index} became a placeholder with the first option"),
+            "UltiSnips has no choices",
+        ));
+    }
+}
+
+fn write_global(output: &mut String, global: &Global, warnings: &mut Vec<Warning>) {
+    let prefix = match global.language {
+        Language::Python => "!p",
+        Language::Vimscript => "!v",
+        language => {
+            warnings.push(Warning {
+                span: global.span.clone(),
+                ..Warning::for_file(
+                    format!("a `global` block of {language} code was left out"),
+                    "UltiSnips only runs Python and Vimscript there",
+                )
+            });
+            return;
+        }
+    };
+
+    write_trivia(output, &global.leading, "#", "");
+    writeln!(output, "global {prefix}").unwrap();
+    writeln!(output, "{}", global.source).unwrap();
+    writeln!(output, "endglobal").unwrap();
+    writeln!(output).unwrap();
+}
+
+/// Writes the `pre_expand` and similar directives, only Python can go in there.
+fn write_actions(output: &mut String, snippet: &Snippet, warnings: &mut Vec<Warning>) {
+    for action in &snippet.actions {
+        let (_, directive) = events()
+            .into_iter()
+            .find(|(event, _)| *event == action.event)
+            .expect("every event has a directive");
+
+        if action.language != Language::Python {
+            warnings.push(Warning::new(
+                snippet,
+                format!("the {} action was left out", action.language),
+                format!("UltiSnips only runs Python in `{directive}`"),
+            ));
+            continue;
+        }
+        writeln!(output, "{directive} \"{}\"", action.source).unwrap();
+    }
+}
+
+/// Warns about contexts [`context_expression`] only checks roughly, or not at all.
+fn warn_contexts(snippet: &Snippet, warnings: &mut Vec<Warning>) {
+    for context in &snippet.contexts {
+        let (message, reason) = match context {
+            Context::InlineMath | Context::DisplayMath => (
+                format!("the {context} context was widened to any math"),
+                "UltiSnips only checks for math as a whole",
+            ),
+            Context::Code { language: Some(_) } => (
+                format!("the {context} context was widened to any code"),
+                "UltiSnips can't tell code blocks of different languages apart",
+            ),
+            Context::Expression { backend, .. } if backend != NAME => (
+                format!("the {context} context was left out"),
+                "UltiSnips can't evaluate it",
+            ),
+            _ => continue,
+        };
+        warnings.push(Warning::new(snippet, message, reason));
+    }
+}
+
+/// Combines all contexts UltiSnips can check into one Python expression, if there are any.
+fn context_expression(contexts: &[Context], modes: &ModeChecks) -> Option<String> {
+    let expressions: Vec<_> = contexts
+        .iter()
+        .filter_map(|context| mode_expression(context, modes))
+        .unique()
+        .collect();
+
+    match expressions.as_slice() {
+        [] => None,
+        [single] => Some(single.to_string()),
+        multiple => Some(multiple.iter().map(|expr| format!("({expr})")).join(" or ")),
+    }
+}
+
+/// Returns a Python expression checking for the given context.
+fn mode_expression<'a>(context: &'a Context, modes: &'a ModeChecks) -> Option<Cow<'a, str>> {
+    if let Some((name, check)) = mode_check(context, modes) {
+        return Some(if modes.helpers {
+            Cow::Owned(format!("{name}()"))
+        } else {
+            Cow::Borrowed(check)
+        });
+    }
+
+    match context {
+        Context::Expression { backend, source } if backend == NAME => Some(Cow::Borrowed(source)),
+        // can't evaluate expressions meant for other backends
+        _ => None,
+    }
+}
+
+/// Returns the name of the mode `context` is about and the check for it, if it's a mode at all.
+fn mode_check<'a>(context: &Context, modes: &'a ModeChecks) -> Option<(&'static str, &'a str)> {
+    match context {
+        Context::Math | Context::InlineMath | Context::DisplayMath => Some(("math", &modes.math)),
+        Context::Text => Some(("text", &modes.text)),
+        Context::Code { .. } => Some(("code", &modes.code)),
+        Context::Expression { .. } => None,
+    }
+}
+
+/// Decides if the mode checks used by any snippet are called as helpers, returning the
+/// `global` block defining them if it has to be written. Files which define them already,
+/// like earlier output read back in, keep using theirs.
+fn mode_helpers(snippets: &SnippetFile, modes: &ModeChecks) -> (bool, Option<Global>) {
+    let used: HashSet<_> = (snippets.snippets.iter())
+        .flat_map(|snippet| &snippet.contexts)
+        .filter_map(|context| mode_check(context, modes))
+        .map(|(name, _)| name)
+        .collect();
+    let functions: Vec<_> = [
+        Context::Math,
+        Context::Text,
+        Context::Code { language: None },
+    ]
+    .iter()
+    .filter_map(|context| mode_check(context, modes))
+    .filter(|(name, _)| used.contains(name))
+    .map(|(name, check)| format!("def {name}():\n\treturn {check}"))
+    .collect();
+
+    let defined = functions.iter().all(|function| {
+        (snippets.globals.iter()).any(|global| global.source.contains(function.as_str()))
+    });
+    if !functions.is_empty() && defined {
+        return (true, None);
+    }
+
+    let global = (modes.helpers && !functions.is_empty()).then(|| Global {
+        language: Language::Python,
+        source: functions.join("\n\n"),
+        leading: Vec::new(),
+        span: Span::default(),
+    });
+    (modes.helpers, global)
+}
+
+fn write_trigger(
+    output: &mut String,
+    snippet: &Snippet,
+    warnings: &mut Vec<Warning>,
+) -> Result<(), SerializeError> {
+    let (trigger, kind) = (snippet.trigger.as_str(), snippet.trigger_kind);
+    let trigger = match kind {
+        TriggerKind::Regex(flags) => {
+            let pattern = regex::translate(trigger, flags, Dialect::Python).map_err(|source| {
+                SerializeError::UntranslatableTrigger {
+                    trigger: trigger.to_string(),
+                    source,
+                }
+            })?;
+
+            // UltiSnips wants regex flags inline, see also de::take_inline_flags
+            match flags.letters() {
+                letters if letters.is_empty() => pattern,
+                letters => Cow::Owned(format!("(?{letters}){pattern}")),
+            }
+        }
+        _ => Cow::Borrowed(trigger),
+    };
+
+    // regexes always need to be quoted, UltiSnips strips the first and last char otherwise
+    if !kind.is_regex() && !trigger.contains(char::is_whitespace) {
         // all fine, no quotes needed
         write!(output, " {}", trigger).unwrap();
         return Ok(());
@@ -56,6 +415,13 @@ fn write_trigger(output: &mut String, trigger: &str) -> Result<(), SerializeErro
         .find(|candidate| !chars_in_trigger.contains(candidate))
     {
         write!(output, " {}{}{}", quote, trigger, quote).unwrap();
+        if quote != '"' {
+            warnings.push(Warning::new(
+                snippet,
+                format!("the trigger was quoted with `{quote}`"),
+                "it contains all more common quote characters",
+            ));
+        }
         return Ok(());
     };
 
@@ -67,26 +433,157 @@ fn write_trigger(output: &mut String, trigger: &str) -> Result<(), SerializeErro
 fn write_description_and_options(
     output: &mut String,
     description: Option<&str>,
-    options: Option<&str>,
+    options: &Options,
+    trigger_kind: TriggerKind,
+    has_context: bool,
 ) {
-    if description.is_some() || options.is_some() {
-        write!(output, " \"{}\"", description.unwrap_or(""),).unwrap();
-
-        if let Some(options) = options {
-            // filter the mode specifiers since UltiSnips doesn't know them
-            let disallowed: HashSet<_> = "tmc".chars().collect();
-            let options: String = options
-                .chars()
-                .filter(|opt| !disallowed.contains(opt))
-                .collect();
+    let mut options = OPTIONS.letters(options);
+    if has_context {
+        options.push('e');
+    }
+
+    options.push_str(match trigger_kind {
+        TriggerKind::Literal => "",
+        TriggerKind::Word => "w",
+        TriggerKind::InWord => "i",
+        TriggerKind::Regex(_) => "r",
+    });
 
+    if description.is_some() || !options.is_empty() {
+        write!(output, " \"{}\"", description.unwrap_or("")).unwrap();
+
+        if !options.is_empty() {
             write!(output, " {options}").unwrap();
         }
     }
 }
 
+/// Writes the body, escaping text as needed. If `nested`, the body is inside a placeholder and
+/// needs to escape `}` additionally.
+fn write_body(output: &mut String, body: &Body, nested: bool) {
+    let mut nodes = body.0.iter().peekable();
+
+    while let Some(node) = nodes.next() {
+        // `$1` followed by `2` would be read back as `$12` otherwise
+        let digit_follows = matches!(
+            nodes.peek(),
+            Some(Node::Text(text)) if text.starts_with(|ch: char| ch.is_ascii_digit()),
+        );
+
+        match node {
+            Node::Text(text) => write_text(output, text, nested),
+            Node::Tabstop(index) | Node::Mirror(index) => {
+                write_index(output, *index, digit_follows)
+            }
+            Node::Final => write_index(output, 0, digit_follows),
+            Node::Transformation { index, transform } => {
+                write!(output, "${{{index}").unwrap();
+                write_transform(output, transform);
+                output.push('}');
+            }
+            Node::Placeholder { index, default } => {
+                write!(output, "${{{index}:").unwrap();
+                write_body(output, default, true);
+                write!(output, "}}").unwrap();
+            }
+            Node::Choice { index, options } => match options.first() {
+                Some(first) => {
+                    write!(output, "${{{index}:").unwrap();
+                    write_text(output, first, true);
+                    write!(output, "}}").unwrap();
+                }
+                None => write_index(output, *index, digit_follows),
+            },
+            Node::Visual { default, transform } => {
+                write!(output, "${{VISUAL").unwrap();
+                if let Some(default) = default {
+                    output.push(':');
+                    for ch in default.chars() {
+                        if matches!(ch, '\\' | '/' | '}') {
+                            output.push('\\');
+                        }
+                        output.push(ch);
+                    }
+                }
+                if let Some(transform) = transform {
+                    write_transform(output, transform);
+                }
+                output.push('}');
+            }
+            Node::Variable(variable) => {
+                let (_, spelling) = variables()
+                    .into_iter()
+                    .find(|(candidate, _)| candidate == variable)
+                    .expect("every variable has a spelling");
+                write!(output, "`!v {spelling}`").unwrap();
+            }
+            Node::Capture(group) => write!(output, "`!p snip.rv = match.group({group})`").unwrap(),
+            Node::Code { language, source } => {
+                let prefix = match language {
+                    Language::Python => "!p",
+                    Language::Vimscript => "!v",
+                    Language::Shell => "",
+                    // warned about already
+                    Language::JavaScript => continue,
+                };
+                // the deserializer only strips a space separating the prefix, not a line break
+                let separator = if prefix.is_empty()
+                    || source.starts_with(|ch: char| ch != ' ' && ch.is_whitespace())
+                {
+                    ""
+                } else {
+                    " "
+                };
+                write!(output, "`{prefix}{separator}{source}`").unwrap();
+            }
+        }
+    }
+}
+
+/// Writes `/regex/format/flags`, without any braces.
+fn write_transform(output: &mut String, transform: &Transform) {
+    let Transform {
+        regex,
+        format,
+        flags,
+    } = transform;
+    write!(output, "/{regex}/{format}/{flags}").unwrap();
+}
+
+fn write_text(output: &mut String, text: &str, nested: bool) {
+    let mut chars = text.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        let needs_escape = match ch {
+            '$' | '`' => true,
+            '}' => nested,
+            // a backslash at the very end could escape whatever node comes next
+            '\\' => chars.peek().is_none_or(|next| ESCAPABLE.contains(*next)),
+            _ => false,
+        };
+
+        if needs_escape {
+            output.push('\\');
+        }
+        output.push(ch);
+    }
+}
+
+fn write_index(output: &mut String, index: u32, braced: bool) {
+    if braced {
+        write!(output, "${{{index}}}").unwrap();
+    } else {
+        write!(output, "${index}").unwrap();
+    }
+}
+
 #[derive(Debug, Error)]
 pub enum SerializeError {
     #[error("The trigger {trigger} has so many special characters that I'm unable to find a proper quote character, in order to insert it properly. Please consider using a more sane trigger, or open a bug report with your trigger and usecase.")]
     CouldNotQuote { trigger: String },
+    #[error("the regex trigger `{trigger}` can't be written for Python")]
+    UntranslatableTrigger {
+        trigger: String,
+        source: TranslateError,
+    },
 }
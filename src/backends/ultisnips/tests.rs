@@ -1,4 +1,17 @@
-use crate::Snippet;
+use std::{collections::BTreeSet, fs, path::Path};
+
+use crate::{
+    action::{Action, Event},
+    body::{Body, Language, Node, Transform},
+    context::Context,
+    diagnostic::Level,
+    options::{Flag, Options},
+    regex::Dialect,
+    span::SpannedError,
+    trigger::{RegexFlags, TriggerKind},
+    trivia::Trivia,
+    Clear, Snippet,
+};
 
 use super::*;
 
@@ -10,15 +23,17 @@ wow
 endsnippet
     "#;
 
-    let ir = UltiSnips.deserialize(input).unwrap();
+    let mut ir = UltiSnips::default().deserialize(input).unwrap();
+    ir.clear_spans();
     assert_eq!(
         ir,
         SnippetFile {
             snippets: vec![Snippet {
                 trigger: "written".to_string(),
-                replacement: "wow".to_string(),
+                body: Body::text("wow"),
                 ..Default::default()
-            }]
+            }],
+            ..Default::default()
         }
     );
 }
@@ -31,16 +46,18 @@ truly
 endsnippet
     "#;
 
-    let ir = UltiSnips.deserialize(input).unwrap();
+    let mut ir = UltiSnips::default().deserialize(input).unwrap();
+    ir.clear_spans();
     assert_eq!(
         ir,
         SnippetFile {
             snippets: vec![Snippet {
                 // yes, this is intended
                 trigger: "oah this is a long trigger wo".to_string(),
-                replacement: "truly".to_string(),
+                body: Body::text("truly"),
                 ..Default::default()
-            }]
+            }],
+            ..Default::default()
         }
     );
 }
@@ -53,17 +70,23 @@ replaced
 endsnippet
     "#;
 
-    let ir = UltiSnips.deserialize(input).unwrap();
+    let mut ir = UltiSnips::default().deserialize(input).unwrap();
+    ir.clear_spans();
     assert_eq!(
         ir,
         SnippetFile {
             snippets: vec![Snippet {
                 trigger: "written".to_string(),
-                replacement: "replaced".to_string(),
+                trigger_kind: TriggerKind::Word,
+                body: Body::text("replaced"),
                 description: Some("long description".to_string()),
-                options: Some("Aw".to_string()),
+                options: Options {
+                    flags: BTreeSet::from([Flag::AutoExpand]),
+                    ..Default::default()
+                },
                 ..Default::default()
             }],
+            ..Default::default()
         },
     );
 }
@@ -76,16 +99,983 @@ b
 endsnippet
     "#;
 
-    let ir = UltiSnips.deserialize(input).unwrap();
+    let mut ir = UltiSnips::default().deserialize(input).unwrap();
+    ir.clear_spans();
     assert_eq!(
         ir,
         SnippetFile {
             snippets: vec![Snippet {
                 trigger: "a".to_string(),
-                replacement: "b".to_string(),
+                body: Body::text("b"),
                 description: Some(String::new()),
                 ..Default::default()
             }],
+            ..Default::default()
         },
     )
 }
+
+#[test]
+fn deserialize_regex() {
+    let input = r#"
+snippet "(?i)(\d)/" "fraction" rA
+\\frac{`!p snip.rv = match.group(1)`}{$1}
+endsnippet
+    "#;
+
+    let ir = UltiSnips::default().deserialize(input).unwrap();
+    let snippet = &ir.snippets[0];
+    assert_eq!(snippet.trigger, r"(\d)/");
+    assert_eq!(
+        snippet.trigger_kind,
+        TriggerKind::Regex(RegexFlags {
+            ignore_case: true,
+            dialect: Some(Dialect::Python),
+            ..Default::default()
+        }),
+    );
+    assert!(snippet.options.has(Flag::AutoExpand));
+}
+
+#[test]
+fn serialize_quotes_regex() {
+    let ir = SnippetFile {
+        snippets: vec![Snippet {
+            trigger: "ab".to_string(),
+            trigger_kind: TriggerKind::Regex(RegexFlags::default()),
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    assert_eq!(
+        UltiSnips::default().serialize(&ir).unwrap(),
+        "snippet \"ab\" \"\" r\n\nendsnippet\n\n",
+    );
+}
+
+#[test]
+fn roundtrip_unknown_options() {
+    let input = "snippet a \"\" bAtXw\nb\nendsnippet\n\n";
+
+    let ir = UltiSnips::default().deserialize(input).unwrap();
+    assert_eq!(ir.snippets[0].options.unknown["ultisnips"], "X");
+    assert_eq!(
+        UltiSnips::default().serialize(&ir).unwrap(),
+        "snippet a \"\" AbtXw\nb\nendsnippet\n\n",
+    );
+}
+
+#[test]
+fn serialize_mode_helpers() {
+    let input = r#"[
+        {trigger: "ff", replacement: "\\frac{$1}{$2}", options: "mA"},
+        {trigger: "mk", replacement: "$$0$", options: "tA"},
+        {trigger: "sr", replacement: "^2", options: "nMA"},
+    ]"#;
+    let ultisnips = UltiSnips {
+        modes: ModeChecks {
+            math: "in_math()".to_string(),
+            helpers: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+
+    let converted = crate::convert(input, &crate::backends::Ols, &ultisnips).unwrap();
+    assert_eq!(
+        converted.output,
+        r#"global !p
+def math():
+	return in_math()
+
+def text():
+	return vim.eval('vimtex#syntax#in_mathzone()') == '0'
+endglobal
+
+context "math()"
+snippet ff "" Aei
+\frac{$1}{$2}
+endsnippet
+
+context "text()"
+snippet mk "" Aei
+\$$0\$
+endsnippet
+
+context "math()"
+snippet sr "" Aei
+^2
+endsnippet
+
+"#,
+    );
+
+    // reading it back in, they're just expressions now, and the helpers a global
+    let ir = ultisnips.deserialize(&converted.output).unwrap();
+    assert_eq!(ir.globals().len(), 1);
+    assert_eq!(ultisnips.serialize(&ir).unwrap(), converted.output);
+}
+
+#[test]
+fn roundtrip_context_line() {
+    let input = r#"context "math()"
+snippet ff "fraction" Ae
+\frac{$1}{$2}
+endsnippet
+
+"#;
+
+    let ir = UltiSnips::default().deserialize(input).unwrap();
+    assert_eq!(
+        ir.snippets[0].contexts,
+        [Context::Expression {
+            backend: "ultisnips".to_string(),
+            source: "math()".to_string(),
+        }],
+    );
+    assert_eq!(UltiSnips::default().serialize(&ir).unwrap(), input);
+}
+
+#[test]
+fn deserialize_inline_context() {
+    let input = r#"
+snippet ff "fraction" "not math()" e
+frac
+endsnippet
+    "#;
+
+    let ir = UltiSnips::default().deserialize(input).unwrap();
+    let snippet = &ir.snippets[0];
+    assert_eq!(snippet.description.as_deref(), Some("fraction"));
+    assert_eq!(
+        snippet.contexts,
+        [Context::Expression {
+            backend: "ultisnips".to_string(),
+            source: "not math()".to_string(),
+        }],
+    );
+    assert!(snippet.options.is_empty());
+}
+
+#[test]
+fn serialize_mode_context() {
+    let ir = SnippetFile {
+        snippets: vec![Snippet {
+            trigger: "ff".to_string(),
+            options: Options {
+                flags: BTreeSet::from([Flag::AutoExpand]),
+                ..Default::default()
+            },
+            contexts: vec![Context::Math],
+            ..Default::default()
+        }],
+        ..Default::default()
+    };
+
+    assert_eq!(
+        UltiSnips::default().serialize(&ir).unwrap(),
+        "context \"vim.eval('vimtex#syntax#in_mathzone()') == '1'\"\nsnippet ff \"\" Ae\n\nendsnippet\n\n",
+    );
+}
+
+#[test]
+fn roundtrip_comments() {
+    let input = r#"# all the snippets
+# you'll ever need
+
+# == greek ==
+snippet a
+\alpha
+endsnippet
+
+snippet b
+\beta
+endsnippet
+
+
+#
+# == misc ==
+snippet hi
+hello
+endsnippet
+
+# the end
+"#;
+
+    let ir = UltiSnips::default().deserialize(input).unwrap();
+    assert_eq!(
+        ir.header,
+        [
+            Trivia::Comment("all the snippets".to_string()),
+            Trivia::Comment("you'll ever need".to_string()),
+        ],
+    );
+    assert_eq!(
+        ir.snippets[2].leading,
+        [
+            Trivia::Blank,
+            Trivia::Comment(String::new()),
+            Trivia::Comment("== misc ==".to_string()),
+        ],
+    );
+    assert_eq!(ir.footer, [Trivia::Comment("the end".to_string())]);
+    assert_eq!(UltiSnips::default().serialize(&ir).unwrap(), input);
+}
+
+#[test]
+fn option_table_covers_all_flags() {
+    for flag in Flag::ALL {
+        assert!(OPTIONS.support(flag).is_some(), "{flag:?} missing");
+    }
+}
+
+#[test]
+fn deserialize_body() {
+    let input = r#"
+snippet beg
+\\begin{$1}
+	${2:${VISUAL}} \$ \`
+\\end{$1}$0
+endsnippet
+    "#;
+
+    let ir = UltiSnips::default().deserialize(input).unwrap();
+    assert_eq!(
+        ir.snippets[0].body,
+        Body(vec![
+            Node::Text("\\begin{".to_string()),
+            Node::Tabstop(1),
+            Node::Text("}\n\t".to_string()),
+            Node::Placeholder {
+                index: 2,
+                default: Body(vec![Node::Visual {
+                    default: None,
+                    transform: None,
+                }]),
+            },
+            Node::Text(" $ `\n\\end{".to_string()),
+            Node::Mirror(1),
+            Node::Text("}".to_string()),
+            Node::Final,
+        ]),
+    );
+}
+
+#[test]
+fn roundtrip_body() {
+    let input = r#"snippet frac
+\frac{${1:a\}b}}{$2}${3}0 `!p snip.rv = "\`"` ${1/(.*)/$1/g}
+endsnippet
+
+"#;
+
+    let ir = UltiSnips::default().deserialize(input).unwrap();
+    assert_eq!(UltiSnips::default().serialize(&ir).unwrap(), input);
+}
+
+#[test]
+fn scope_from_path() {
+    let scope = |path| UltiSnips::default().scope_from_path(Path::new(path));
+
+    assert_eq!(scope("UltiSnips/tex.snippets").as_deref(), Some("tex"));
+    assert_eq!(scope("tex_math.snippets").as_deref(), Some("tex"));
+    assert_eq!(scope("all.snippets"), None);
+    assert_eq!(scope("tex.json"), None);
+}
+
+#[test]
+fn write_splits_scopes_into_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+
+    let ir = SnippetFile {
+        scope: Some("tex".to_string()),
+        snippets: vec![
+            Snippet {
+                trigger: "a".to_string(),
+                ..Default::default()
+            },
+            Snippet {
+                trigger: "b".to_string(),
+                scope: Some("markdown".to_string()),
+                ..Default::default()
+            },
+        ],
+        ..Default::default()
+    };
+    let mut warnings = Vec::new();
+    UltiSnips::default().write(dir, &ir, &mut warnings).unwrap();
+
+    let tex = fs::read_to_string(dir.join("tex.snippets")).unwrap();
+    let markdown = fs::read_to_string(dir.join("markdown.snippets")).unwrap();
+    assert_eq!(tex, "snippet a\n\nendsnippet\n\n");
+    assert_eq!(markdown, "snippet b\n\nendsnippet\n\n");
+    assert!(warnings.is_empty());
+
+    // but a single file mixes them
+    UltiSnips::default()
+        .write(&dir.join("tex.snippets"), &ir, &mut warnings)
+        .unwrap();
+    assert_eq!(warnings.len(), 1);
+    assert!(warnings[0].message.contains("`markdown`, `tex`"));
+}
+
+#[test]
+fn deserialize_records_spans() {
+    let input = "# header\n\nsnippet a\nb\nendsnippet\n\n  snippet c\nd\n  endsnippet\n\n\
+                 global !p\nx = 1\n  endglobal\n";
+
+    let ir = UltiSnips::default().deserialize(input).unwrap();
+    let spans: Vec<_> = (ir.snippets.iter().map(|snippet| &snippet.span))
+        .chain(ir.globals.iter().map(|global| &global.span))
+        .map(|span| {
+            (
+                span.start.line,
+                span.start.column,
+                span.end.line,
+                span.end.column,
+            )
+        })
+        .collect();
+    assert_eq!(spans, [(3, 1, 5, 11), (7, 3, 9, 13), (11, 1, 13, 12)]);
+}
+
+#[test]
+fn errors_point_at_their_source() {
+    let input = "snippet a\nb\nendsnippet\n\n\tsnipet c\n";
+
+    let err = UltiSnips::default().deserialize(input).unwrap_err();
+    let err = err.downcast_ref::<SpannedError>().unwrap();
+    assert_eq!(
+        crate::diagnostic::render(Level::Error, &err.to_string(), &err.span, input),
+        "error: unknown directive: `snipet`\n \
+         --> 5:2\n  \
+         |\n\
+         5 |     snipet c\n  \
+         |     ^^^^^^\n",
+    );
+}
+
+#[test]
+fn roundtrip_actions() {
+    let input = r#"pre_expand "snip.buffer[snip.line] = ''"
+post_jump "snip.cursor.set(0, 0)"
+snippet a
+b
+endsnippet
+
+snippet c
+d
+endsnippet
+
+"#;
+
+    let ir = UltiSnips::default().deserialize(input).unwrap();
+    assert_eq!(
+        ir.snippets[0].actions,
+        [
+            Action {
+                event: Event::PreExpand,
+                language: Language::Python,
+                source: "snip.buffer[snip.line] = ''".to_string(),
+            },
+            Action {
+                event: Event::PostJump,
+                language: Language::Python,
+                source: "snip.cursor.set(0, 0)".to_string(),
+            },
+        ],
+    );
+    assert!(ir.snippets[1].actions.is_empty());
+    assert_eq!(UltiSnips::default().serialize(&ir).unwrap(), input);
+}
+
+#[test]
+fn roundtrip_all_directives() {
+    let input = r#"extends tex
+
+priority -10
+clearsnippets
+priority 10
+clearsnippets fr sum
+
+post_expand "snip.expand_anon('x')"
+context "math()"
+snippet fr "fraction" e
+\frac{$1}{$2}
+endsnippet
+
+"#;
+
+    let ir = UltiSnips::default().deserialize(input).unwrap();
+    assert_eq!(
+        ir.clears(),
+        [
+            Clear {
+                triggers: Vec::new(),
+                priority: Some(-10),
+            },
+            Clear {
+                triggers: vec!["fr".to_string(), "sum".to_string()],
+                priority: Some(10),
+            },
+        ],
+    );
+    let snippet = &ir.snippets[0];
+    assert_eq!(snippet.priority, Some(10));
+    assert_eq!(snippet.actions[0].event, Event::PostExpand);
+    assert_eq!(
+        snippet.contexts,
+        [Context::Expression {
+            backend: "ultisnips".to_string(),
+            source: "math()".to_string(),
+        }],
+    );
+    assert_eq!(UltiSnips::default().serialize(&ir).unwrap(), input);
+
+    let mut warnings = Vec::new();
+    crate::backends::Ols
+        .serialize_with_warnings(&ir, &mut warnings)
+        .unwrap();
+    // clearsnippets, extends, the action and the context
+    assert_eq!(warnings.len(), 4);
+}
+
+#[test]
+fn directives_need_quoted_expressions() {
+    for directive in ["context", "pre_expand", "post_expand", "post_jump"] {
+        let input = format!("{directive} math()\nsnippet a\nb\nendsnippet\n");
+        let err = UltiSnips::default().deserialize(&input).unwrap_err();
+        assert!(err.root_cause().to_string().contains(directive));
+    }
+}
+
+#[test]
+fn clearsnippets_applies_to_extended() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    fs::write(
+        dir.join("tex.snippets"),
+        "snippet fr\nold\nendsnippet\n\npriority 20\nsnippet sum\nkept\nendsnippet\n",
+    )
+    .unwrap();
+
+    let input = "extends tex\n\npriority 10\nclearsnippets fr sum\n\nsnippet fr\nnew\nendsnippet\n";
+    let mut ir = UltiSnips::default().deserialize(input).unwrap();
+    UltiSnips::default().resolve_extends(&mut ir, dir).unwrap();
+
+    let bodies: Vec<_> = ir.iter().map(|snippet| snippet.body().clone()).collect();
+    assert_eq!(bodies, [Body::text("new"), Body::text("kept")]);
+}
+
+#[test]
+fn roundtrip_visual() {
+    let input = r#"snippet a
+${VISUAL} ${VISUAL:no \} selection} ${VISUAL/(\w+)\/x/\u$1/g} ${VISUAL:a\/b/^/> /}
+endsnippet
+
+"#;
+
+    let ir = UltiSnips::default().deserialize(input).unwrap();
+    let visuals: Vec<_> = ir.snippets[0]
+        .body
+        .0
+        .iter()
+        .filter_map(|node| match node {
+            Node::Visual { default, transform } => Some((default.as_deref(), transform.clone())),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        visuals,
+        [
+            (None, None),
+            (Some("no } selection"), None),
+            (
+                None,
+                Some(Transform {
+                    regex: r"(\w+)\/x".to_string(),
+                    format: r"\u$1".to_string(),
+                    flags: "g".to_string(),
+                }),
+            ),
+            (
+                Some("a/b"),
+                Some(Transform {
+                    regex: "^".to_string(),
+                    format: "> ".to_string(),
+                    flags: String::new(),
+                }),
+            ),
+        ],
+    );
+    assert_eq!(UltiSnips::default().serialize(&ir).unwrap(), input);
+}
+
+#[test]
+fn choice_degrades() {
+    let input = "snippet sec\n\\\\${1|section,sub\\,section|}{$2}\nendsnippet\n\n";
+    let ir = UltiSnips::default().deserialize(input).unwrap();
+    assert_eq!(
+        ir.snippets[0].body,
+        Body(vec![
+            Node::Text("\\".to_string()),
+            Node::Choice {
+                index: 1,
+                options: vec!["section".to_string(), "sub,section".to_string()],
+            },
+            Node::Text("{".to_string()),
+            Node::Tabstop(2),
+            Node::Text("}".to_string()),
+        ]),
+    );
+
+    let mut warnings = Vec::new();
+    assert_eq!(
+        UltiSnips::default()
+            .serialize_with_warnings(&ir, &mut warnings)
+            .unwrap(),
+        "# $1 is one of: section, sub,section\nsnippet sec\n\\\\${1:section}{$2}\nendsnippet\n\n",
+    );
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].trigger.as_deref(), Some("sec"));
+}
+
+#[test]
+fn variables_are_recognised() {
+    let input = r#"snippet hdr
+% `!v expand("%:t")` `!p snip.rv = snip.basename`, `!v strftime('%Y')` `!v system('date')`
+endsnippet
+"#;
+
+    let ir = UltiSnips::default().deserialize(input).unwrap();
+    let variables: Vec<_> = ir.snippets[0]
+        .body
+        .0
+        .iter()
+        .filter_map(|node| match node {
+            Node::Variable(variable) => Some(*variable),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        variables,
+        [Variable::FileName, Variable::FileStem, Variable::Year]
+    );
+
+    let output = UltiSnips::default().serialize(&ir).unwrap();
+    assert!(output.contains(
+        "% `!v expand('%:t')` `!v expand('%:t:r')`, `!v strftime('%Y')` `!v system('date')`"
+    ));
+}
+
+#[test]
+fn roundtrip_transformation() {
+    let input = r#"snippet beg
+\begin{${1:env}}
+	$0
+\end{${1/(\w)(.*)/\u$1$2/g}}
+endsnippet
+
+"#;
+
+    let ir = UltiSnips::default().deserialize(input).unwrap();
+    assert!(ir.snippets[0].body.0.contains(&Node::Transformation {
+        index: 1,
+        transform: Transform {
+            regex: r"(\w)(.*)".to_string(),
+            format: r"\u$1$2".to_string(),
+            flags: "g".to_string(),
+        },
+    }));
+    assert_eq!(UltiSnips::default().serialize(&ir).unwrap(), input);
+
+    let mut warnings = Vec::new();
+    let ols = crate::backends::Ols
+        .serialize_with_warnings(&ir, &mut warnings)
+        .unwrap();
+    assert!(ols.contains(r"\\end{$1}"));
+    assert_eq!(warnings.len(), 1);
+}
+
+#[test]
+fn code_keeps_its_language() {
+    let input = r#"snippet now
+`!p snip.rv = datetime.now()` `!v g:name` `date +%s` `!p
+import os
+snip.rv = os.getcwd()`
+endsnippet
+
+"#;
+
+    let ir = UltiSnips::default().deserialize(input).unwrap();
+    let code: Vec<_> = ir.snippets[0]
+        .body
+        .0
+        .iter()
+        .filter_map(|node| match node {
+            Node::Code { language, source } => Some((*language, source.as_str())),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(
+        code,
+        [
+            (Language::Python, "snip.rv = datetime.now()"),
+            (Language::Vimscript, "g:name"),
+            (Language::Shell, "date +%s"),
+            (Language::Python, "\nimport os\nsnip.rv = os.getcwd()"),
+        ],
+    );
+    assert_eq!(UltiSnips::default().serialize(&ir).unwrap(), input);
+
+    let mut warnings = Vec::new();
+    let ols = crate::backends::Ols
+        .serialize_with_warnings(&ir, &mut warnings)
+        .unwrap();
+    assert!(ols.contains(r#""replacement":"   ""#));
+    assert_eq!(warnings.len(), 3);
+    let ports = crate::manual_ports(&ir, &crate::backends::Ols);
+    assert_eq!(
+        ports[0].1,
+        [Language::Python, Language::Vimscript, Language::Shell]
+    );
+}
+
+#[test]
+fn roundtrip_extends() {
+    let input = r#"# tex, but also everything from markdown
+
+extends markdown, html
+
+snippet a
+b
+endsnippet
+
+"#;
+
+    let ir = UltiSnips::default().deserialize(input).unwrap();
+    assert_eq!(ir.extends(), ["markdown", "html"]);
+    assert_eq!(
+        ir.header(),
+        [Trivia::Comment(
+            "tex, but also everything from markdown".to_string()
+        )]
+    );
+    assert_eq!(UltiSnips::default().serialize(&ir).unwrap(), input);
+}
+
+#[test]
+fn resolve_extends() {
+    let dir = tempfile::tempdir().unwrap();
+    let dir = dir.path();
+    fs::create_dir_all(dir.join("markdown")).unwrap();
+    let files = [
+        ("tex.snippets", "extends markdown\n"),
+        (
+            "markdown.snippets",
+            "extends tex, html\n\npriority -10\nsnippet a\nmarkdown\nendsnippet\n",
+        ),
+        ("markdown/lists.snippets", "snippet b\nlist\nendsnippet\n"),
+        ("markdown_x.snippets", "snippet c\nx\nendsnippet\n"),
+        (
+            "markdownish.snippets",
+            "snippet d\nnot markdown\nendsnippet\n",
+        ),
+    ];
+    for (path, content) in files {
+        fs::write(dir.join(path), content).unwrap();
+    }
+
+    let mut ir = UltiSnips::default()
+        .deserialize(&fs::read_to_string(dir.join("tex.snippets")).unwrap())
+        .unwrap();
+    ir.set_scope(Some("tex".to_string()));
+    ir.push(Snippet::new("a", Body::text("tex")).with_priority(0));
+    UltiSnips::default().resolve_extends(&mut ir, dir).unwrap();
+
+    let triggers: Vec<_> = ir
+        .iter()
+        .map(|snippet| (snippet.trigger(), snippet.priority()))
+        .collect();
+    assert_eq!(
+        triggers,
+        [("a", Some(0)), ("b", None), ("a", Some(-10)), ("c", None)]
+    );
+    assert!(ir.extends().is_empty());
+}
+
+#[test]
+fn roundtrip_global() {
+    let input = r#"# helpers for math
+
+global !p
+def math():
+	return vim.eval('vimtex#syntax#in_mathzone()') == '1'
+endglobal
+
+context "math()"
+snippet ff "fraction" e
+\frac{$1}{$2}
+endsnippet
+
+"#;
+
+    let ir = UltiSnips::default().deserialize(input).unwrap();
+    assert_eq!(ir.globals().len(), 1);
+    assert_eq!(ir.globals()[0].language, Language::Python);
+    assert_eq!(
+        ir.globals()[0].source,
+        "def math():\n\treturn vim.eval('vimtex#syntax#in_mathzone()') == '1'",
+    );
+    assert_eq!(UltiSnips::default().serialize(&ir).unwrap(), input);
+
+    let mut warnings = Vec::new();
+    crate::backends::Ols
+        .serialize_with_warnings(&ir, &mut warnings)
+        .unwrap();
+    // math() is the default math check, so only the global is lost
+    assert_eq!(ir.snippets()[0].contexts(), [Context::Math]);
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].trigger, None);
+    assert_eq!(warnings[0].span.start.line, 3);
+}
+
+#[test]
+fn malformed_input_errors() {
+    for input in [
+        "snippet a\nno end in sight",
+        "snippet\nendsnippet",
+        "snippet \" \"\nendsnippet",
+        "snippet x \"\" \"\nendsnippet",
+        "global !p\nnever closed",
+    ] {
+        assert!(
+            UltiSnips::default().deserialize(input).is_err(),
+            "{input:?}"
+        );
+    }
+}
+
+#[test]
+fn regex_triggers_translate_to_javascript() {
+    let convert = |trigger: &str| {
+        let input = format!("snippet \"{trigger}\" \"\" r\nendsnippet\n");
+        let output = crate::convert(&input, &UltiSnips::default(), &crate::backends::Ols)?;
+        let entries: serde_json::Value = json5::from_str(&output.output)?;
+        anyhow::Ok(entries[0]["trigger"].as_str().unwrap().to_string())
+    };
+
+    assert_eq!(
+        convert(r"\A(?P<n>\d)(?#twice)(?P=n)").unwrap(),
+        r"^(?<n>\d)\k<n>",
+    );
+    assert_eq!(
+        convert(r"(?u)x{,3}[]a]\U0001F600").unwrap(),
+        r"x{0,3}[\]a]\u{1f600}",
+    );
+    // without `u`, JavaScript doesn't know anything beyond \uffff
+    assert!(convert(r"\U0001F600").is_err());
+
+    for untranslatable in [r"(?>a)b", r"a++", r"(?x)a b", r"(?(1)a|b)", r"\N{BULLET}"] {
+        let err = convert(untranslatable).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("JavaScript has no equivalent"),
+            "{untranslatable}: {err:#}",
+        );
+    }
+}
+
+#[test]
+fn layout_groups_and_sorts() {
+    let input = r#"priority 10
+snippet b "greek: beta"
+\beta
+endsnippet
+
+priority 0
+snippet sum "big: sum"
+\sum
+endsnippet
+
+priority 10
+snippet a "greek: alpha"
+\alpha
+endsnippet
+
+priority 0
+snippet prod "big: product"
+\prod
+endsnippet
+"#;
+    let mut ultisnips = UltiSnips {
+        layout: Layout {
+            group_by: Some(GroupBy::Priority),
+            sort: true,
+            headers: true,
+        },
+        ..Default::default()
+    };
+
+    let ir = ultisnips.deserialize(input).unwrap();
+    let output = ultisnips.serialize(&ir).unwrap();
+    assert_eq!(
+        output,
+        r#"# --- priority 0 ---
+snippet prod "big: product"
+\prod
+endsnippet
+
+snippet sum "big: sum"
+\sum
+endsnippet
+
+# --- priority 10 ---
+priority 10
+snippet a "greek: alpha"
+\alpha
+endsnippet
+
+snippet b "greek: beta"
+\beta
+endsnippet
+
+"#,
+    );
+    // the headers don't pile up
+    let reread = ultisnips.deserialize(&output).unwrap();
+    assert_eq!(ultisnips.serialize(&reread).unwrap(), output);
+
+    ultisnips.layout.group_by = Some(GroupBy::Description);
+    ultisnips.layout.sort = false;
+    let headers: Vec<_> = (ultisnips.serialize(&ir).unwrap().lines())
+        .filter(|line| line.starts_with('#'))
+        .map(str::to_string)
+        .collect();
+    assert_eq!(headers, ["# --- greek ---", "# --- big ---"]);
+}
+
+#[test]
+fn lossy_conversions_warn() {
+    let input = "snippet 'a \"b' \"\" q\nbody\nendsnippet\n";
+    let converted = crate::convert(input, &UltiSnips::default(), &UltiSnips::default()).unwrap();
+    let messages: Vec<_> = (converted.warnings.iter())
+        .map(|warning| warning.message.as_str())
+        .collect();
+    assert_eq!(
+        messages,
+        [
+            "the option letters `q` are only kept for `ultisnips`",
+            "the trigger was quoted with `'`",
+        ],
+    );
+    assert_eq!(converted.warnings[0].span.start.line, 1);
+
+    let input = r#"[{trigger: "vis", replacement: "(${VISUAL})", options: "vn"}]"#;
+    let converted = crate::convert(input, &crate::backends::Ols, &UltiSnips::default()).unwrap();
+    let reasons: Vec<_> = (converted.warnings.iter())
+        .map(|warning| warning.reason.as_str())
+        .collect();
+    assert_eq!(
+        reasons,
+        [
+            "UltiSnips only checks for math as a whole",
+            "every snippet can use the selection through ${VISUAL}",
+        ],
+    );
+}
+
+#[test]
+fn mode_contexts_roundtrip() {
+    let input = r#"[{trigger: "ff", replacement: "x", options: "mA"}]"#;
+    let ols = crate::backends::Ols;
+
+    let custom = UltiSnips {
+        modes: ModeChecks {
+            text: "in_text()".to_string(),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let helpers = UltiSnips {
+        modes: ModeChecks {
+            helpers: true,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    for backend in [UltiSnips::default(), custom, helpers] {
+        let ultisnips = crate::convert(input, &ols, &backend).unwrap().output;
+        let back = crate::convert(&ultisnips, &backend, &ols).unwrap();
+        assert_eq!(
+            back.output,
+            r#"[{"trigger":"ff","replacement":"x","options":"mA","description":""}]"#,
+        );
+
+        // reading its own output again doesn't define the helpers twice
+        let again = crate::convert(&ultisnips, &backend, &backend).unwrap();
+        assert_eq!(again.output, ultisnips);
+    }
+
+    let input = r#"[{trigger: "ff", replacement: "x", options: "mt"}]"#;
+    let ultisnips = crate::convert(input, &ols, &UltiSnips::default()).unwrap();
+    assert!(ultisnips.output.contains(") or ("));
+    let back = crate::convert(&ultisnips.output, &UltiSnips::default(), &ols).unwrap();
+    assert!(back.output.contains(r#""options":"mt""#));
+}
+
+#[test]
+fn layout_is_idempotent() {
+    let input = r#"[
+        {trigger: "ff", replacement: "\\frac{$1}{$2}", options: "mA"},
+        {trigger: "dm", replacement: "$$$0$$", options: "tA"},
+        {trigger: "sr", replacement: "^2", options: "mA"},
+    ]"#;
+    let mut ultisnips = UltiSnips {
+        layout: Layout {
+            group_by: Some(GroupBy::Context),
+            sort: true,
+            headers: true,
+        },
+        ..Default::default()
+    };
+
+    let output = crate::convert(input, &crate::backends::Ols, &ultisnips)
+        .unwrap()
+        .output;
+    assert!(output.starts_with("# --- math ---\n"));
+    let again = crate::convert(&output, &ultisnips, &ultisnips)
+        .unwrap()
+        .output;
+    assert_eq!(again, output);
+
+    // headers of another layout don't stick around either
+    ultisnips.layout.group_by = Some(GroupBy::Priority);
+    let regrouped = crate::convert(&output, &ultisnips, &ultisnips)
+        .unwrap()
+        .output;
+    assert!(!regrouped.contains("--- math ---"));
+    assert!(regrouped.starts_with("# --- priority 0 ---\n"));
+}
+
+#[test]
+fn comments_stay_above_priority() {
+    let input = r#"snippet a
+a
+endsnippet
+
+# the important ones
+priority 10
+snippet b
+b
+endsnippet
+
+"#;
+    let ir = UltiSnips::default().deserialize(input).unwrap();
+    assert_eq!(
+        ir.snippets()[1].leading(),
+        [Trivia::Comment("the important ones".to_string())],
+    );
+    assert_eq!(UltiSnips::default().serialize(&ir).unwrap(), input);
+}
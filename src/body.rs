@@ -0,0 +1,201 @@
+//! What a snippet expands to, as a tree instead of backend-specific syntax
+//!
+//! Backends parse their replacement syntax into a [`Body`] and render it back from one, so a
+//! tabstop written as `${1:foo}` in one format ends up in whatever spelling the target format
+//! uses, instead of being copied over verbatim and hoping both formats agree.
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt,
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::variable::Variable;
+
+/// The parsed replacement of a snippet.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(transparent)]
+pub struct Body(pub Vec<Node>);
+
+/// One segment of a [`Body`].
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Node {
+    /// Literal text, without any escaping.
+    Text(String),
+    /// A tabstop without default text, like `$1`.
+    Tabstop(u32),
+    /// A tabstop with default text, like `${1:foo}`. The default may contain further nodes.
+    Placeholder { index: u32, default: Body },
+    /// A tabstop where the user picks one of the given options, like `${1|a,b,c|}`.
+    Choice { index: u32, options: Vec<String> },
+    /// A value like the file name or date, filled in on expansion.
+    Variable(Variable),
+    /// Whatever the capture group with this number matched in a regex trigger. Numbered like
+    /// in the regex itself, so the first group is 1 and 0 is the whole match.
+    Capture(u32),
+    /// Repeats whatever the user typed into the tabstop with the same index.
+    Mirror(u32),
+    /// Like a mirror, but with a regex applied to what the user typed, like
+    /// `${1/(\w+)/\u$1/g}`.
+    Transformation { index: u32, transform: Transform },
+    /// Where the cursor ends up after all other tabstops, `$0`.
+    Final,
+    /// The text that was selected before expanding the snippet, like `${VISUAL}`.
+    Visual {
+        /// Inserted instead if nothing was selected.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        default: Option<String>,
+        /// Applied to the selection before inserting it.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        transform: Option<Transform>,
+    },
+    /// Code run on expansion, inserting whatever it outputs, like `` `!p snip.rv = "hi"` ``.
+    Code { language: Language, source: String },
+}
+
+/// What a [`Node::Code`] is written in.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Language {
+    Python,
+    Vimscript,
+    /// Whatever the shell of the user is.
+    Shell,
+    JavaScript,
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Language::Python => "Python",
+            Language::Vimscript => "Vim script",
+            Language::Shell => "shell",
+            Language::JavaScript => "JavaScript",
+        })
+    }
+}
+
+/// A regex replacement applied to some text before it's inserted, like the `/(\w+)/\u$1/g` in
+/// `${VISUAL/(\w+)/\u$1/g}`.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct Transform {
+    /// The pattern, exactly as written, including any escaped `/`.
+    pub regex: String,
+    /// What matches are replaced with. `$1` refers to groups everywhere, the rest of the
+    /// syntax (case changes, conditionals) depends on the engine that parsed it.
+    pub format: String,
+    /// Letters like `g` for replacing all matches, as written.
+    pub flags: String,
+}
+
+impl Body {
+    /// Creates a body consisting of only the given literal text.
+    pub fn text(text: impl Into<String>) -> Self {
+        let text = text.into();
+        if text.is_empty() {
+            Self::default()
+        } else {
+            Self(vec![Node::Text(text)])
+        }
+    }
+
+    /// Returns the index and options of every [`Node::Choice`], including nested ones.
+    pub fn choices(&self) -> Vec<(u32, &[String])> {
+        let mut choices = Vec::new();
+        self.walk(&mut |node| {
+            if let Node::Choice { index, options } = node {
+                choices.push((*index, options.as_slice()));
+            }
+        });
+        choices
+    }
+
+    /// Returns every language [`Node::Code`] in this body is written in, including nested ones.
+    pub fn languages(&self) -> BTreeSet<Language> {
+        let mut languages = BTreeSet::new();
+        self.walk(&mut |node| {
+            if let Node::Code { language, .. } = node {
+                languages.insert(*language);
+            }
+        });
+        languages
+    }
+
+    /// Calls `visit` for every node, including the ones nested in placeholders.
+    pub fn walk<'a>(&'a self, visit: &mut impl FnMut(&'a Node)) {
+        for node in &self.0 {
+            visit(node);
+            if let Node::Placeholder { default, .. } = node {
+                default.walk(visit);
+            }
+        }
+    }
+
+    /// Turns all but the defining occurrence of each tabstop into a [`Node::Mirror`].
+    ///
+    /// Parsers only see `$1` and can't know if it's the tabstop itself or a mirror of it, so
+    /// they emit [`Node::Tabstop`] everywhere and call this afterwards. If a tabstop has a
+    /// [`Node::Placeholder`] or [`Node::Choice`] anywhere, that one is the defining one,
+    /// otherwise it's the first occurrence.
+    pub fn resolve_mirrors(&mut self) {
+        let mut defined = HashSet::new();
+        self.collect_placeholders(&mut defined);
+        self.mark_mirrors(&mut defined);
+    }
+
+    fn collect_placeholders(&self, defined: &mut HashSet<u32>) {
+        self.walk(&mut |node| {
+            if let Node::Placeholder { index, .. } | Node::Choice { index, .. } = node {
+                defined.insert(*index);
+            }
+        });
+    }
+
+    fn mark_mirrors(&mut self, defined: &mut HashSet<u32>) {
+        for node in &mut self.0 {
+            match node {
+                Node::Tabstop(index) if !defined.insert(*index) => *node = Node::Mirror(*index),
+                Node::Placeholder { default, .. } => default.mark_mirrors(defined),
+                _ => (),
+            }
+        }
+    }
+}
+
+/// Helps parsers accumulating text between other nodes.
+#[derive(Debug, Default)]
+pub(crate) struct BodyBuilder {
+    nodes: Vec<Node>,
+    text: String,
+}
+
+impl BodyBuilder {
+    pub fn push_char(&mut self, ch: char) {
+        self.text.push(ch);
+    }
+
+    pub fn push_str(&mut self, text: &str) {
+        self.text.push_str(text);
+    }
+
+    pub fn push(&mut self, node: Node) {
+        self.flush_text();
+        self.nodes.push(node);
+    }
+
+    pub fn finish(mut self) -> Body {
+        self.flush_text();
+        Body(self.nodes)
+    }
+
+    fn flush_text(&mut self) {
+        if !self.text.is_empty() {
+            self.nodes.push(Node::Text(std::mem::take(&mut self.text)));
+        }
+    }
+}
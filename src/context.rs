@@ -0,0 +1,44 @@
+//! Where in a document a snippet is allowed to expand
+
+use std::fmt;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A condition that has to hold at the cursor for a snippet to expand.
+///
+/// A snippet can have multiple of these, in which case it's enough if any of them holds. A
+/// snippet without any is allowed everywhere.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Context {
+    /// In any kind of math.
+    Math,
+    /// In inline math, like `$...$`.
+    InlineMath,
+    /// In display math, like `$$...$$`.
+    DisplayMath,
+    /// Outside of math and code.
+    Text,
+    /// In a code block, optionally only one of the given language.
+    Code { language: Option<String> },
+    /// An expression that only the named backend knows how to evaluate, like a Python
+    /// expression for UltiSnips.
+    Expression { backend: String, source: String },
+}
+
+impl fmt::Display for Context {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Context::Math => f.write_str("math"),
+            Context::InlineMath => f.write_str("inline math"),
+            Context::DisplayMath => f.write_str("display math"),
+            Context::Text => f.write_str("text"),
+            Context::Code { language: None } => f.write_str("code"),
+            Context::Code {
+                language: Some(language),
+            } => write!(f, "{language} code"),
+            Context::Expression { backend, source } => write!(f, "`{source}` of `{backend}`"),
+        }
+    }
+}
@@ -0,0 +1,126 @@
+//! Printing errors and warnings the way rustc does, with the offending line underlined
+
+use std::fmt::{self, Write};
+
+use serde::Serialize;
+
+use crate::{span::Span, Snippet};
+
+/// Tabs would throw off the underline, so they're replaced by this many spaces.
+const TAB_WIDTH: usize = 4;
+
+/// How bad a diagnostic is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Level {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Level::Error => "error",
+            Level::Warning => "warning",
+        })
+    }
+}
+
+/// Something a backend couldn't carry over exactly, but which didn't stop it either, like a
+/// feature the target format lacks.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Warning {
+    /// Trigger of the snippet it's about, [`None`] if it's about the whole file.
+    pub trigger: Option<String>,
+    /// Where that snippet came from, unknown if it was built by hand.
+    pub span: Span,
+    /// What was lost or changed.
+    pub message: String,
+    /// Why, usually what the format lacks.
+    pub reason: String,
+}
+
+impl Warning {
+    pub fn new(snippet: &Snippet, message: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            trigger: Some(snippet.trigger().to_string()),
+            span: snippet.span().clone(),
+            message: message.into(),
+            reason: reason.into(),
+        }
+    }
+
+    /// A warning about the file as a whole, not any snippet in particular.
+    pub fn for_file(message: impl Into<String>, reason: impl Into<String>) -> Self {
+        Self {
+            trigger: None,
+            span: Span::default(),
+            message: message.into(),
+            reason: reason.into(),
+        }
+    }
+
+    /// Renders the warning, underlining the snippet in `source` if given. `source` has to be
+    /// the file the span points into.
+    pub fn render(&self, source: Option<&str>) -> String {
+        let message = format!("{}, as {}", self.message, self.reason);
+        let message = match &self.trigger {
+            Some(trigger) => format!("{message} (in snippet `{trigger}`)"),
+            None => message,
+        };
+        match source {
+            Some(source) if !self.span.is_unknown() => {
+                render(Level::Warning, &message, &self.span, source)
+            }
+            _ if !self.span.is_unknown() => format!("warning: {message}\n  --> {}\n", self.span),
+            _ => format!("warning: {message}\n"),
+        }
+    }
+}
+
+/// Renders `message` pointing at `span` in `source`, like
+///
+/// ```text
+/// error: unknown directive: `snipet`
+///  --> tex.snippets:3:1
+///   |
+/// 3 | snipet a
+///   | ^^^^^^
+/// ```
+///
+/// Spans over multiple lines are only underlined on their first line.
+pub fn render(level: Level, message: &str, span: &Span, source: &str) -> String {
+    let line_number = span.start.line.to_string();
+    let gutter = " ".repeat(line_number.len());
+
+    let line = source.lines().nth(span.start.line - 1).unwrap_or_default();
+    let before: String = line.chars().take(span.start.column - 1).collect();
+    let underlined = if span.end.line == span.start.line {
+        span.end.column.saturating_sub(span.start.column)
+    } else {
+        line.chars().count() + 1 - span.start.column
+    };
+    let underlined: String = line
+        .chars()
+        .skip(span.start.column - 1)
+        .take(underlined)
+        .collect();
+
+    let mut output = String::new();
+    writeln!(output, "{level}: {message}").unwrap();
+    writeln!(output, "{gutter}--> {span}").unwrap();
+    writeln!(output, "{gutter} |").unwrap();
+    writeln!(output, "{line_number} | {}", expand_tabs(line)).unwrap();
+    writeln!(
+        output,
+        "{gutter} | {}{}",
+        " ".repeat(expand_tabs(&before).chars().count()),
+        // empty spans still need something to point with
+        "^".repeat(expand_tabs(&underlined).chars().count().max(1)),
+    )
+    .unwrap();
+    output
+}
+
+fn expand_tabs(text: &str) -> String {
+    text.replace('\t', &" ".repeat(TAB_WIDTH))
+}
@@ -1,65 +1,684 @@
-use std::{collections::HashMap, fs, path::PathBuf};
+//! Converting snippets between the formats of different snippet engines
+//!
+//! Every format is a [`Backend`], reading into and writing from the same
+//! intermediate representation, a [`SnippetFile`]. [`convert`] does both in one go:
+//!
+//! ```
+//! use snippets_everywhere::{backends::{Ols, UltiSnips}, convert};
+//!
+//! let ols = convert("snippet fr \"fraction\"\n\\frac{$1}{$2}\nendsnippet\n", &UltiSnips::default(), &Ols)?;
+//! assert!(ols.output.contains(r#""replacement":"\\frac{$1}{$2}""#));
+//! assert!(ols.warnings.is_empty());
+//! # Ok::<(), anyhow::Error>(())
+//! ```
+//!
+//! The IR can also be built and inspected directly:
+//!
+//! ```
+//! use snippets_everywhere::{body::Body, options::Flag, Snippet, SnippetFile};
+//!
+//! let mut file = SnippetFile::new();
+//! file.push(Snippet::new("alpha", Body::text("\\alpha")).with_flag(Flag::AutoExpand));
+//!
+//! for snippet in &mut file {
+//!     snippet.set_description(Some(format!("types {}", snippet.trigger())));
+//! }
+//! assert_eq!(file.snippets()[0].description(), Some("types alpha"));
+//! ```
 
-use anyhow::{anyhow, Context, Result};
-use backends::Backend;
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs,
+    path::{Path, PathBuf},
+    slice, vec,
+};
+
+use action::Action;
+use anyhow::{anyhow, bail, Context, Result};
+use backends::{Backend, UltiSnips};
+use body::{Body, Language};
 use clap::ArgMatches;
+use diagnostic::{Level, Warning};
+use itertools::Itertools;
+use options::{Flag, Options};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use span::{Span, SpannedError};
+use trigger::TriggerKind;
+use trivia::Trivia;
 
+pub mod action;
 pub mod backends;
+pub mod body;
+pub mod context;
+pub mod diagnostic;
+pub mod options;
+pub mod regex;
+pub mod span;
+pub mod trigger;
+pub mod trivia;
 pub mod ui;
+pub mod variable;
 
 pub fn run() -> Result<()> {
-    let backends = backends::all();
+    let mut backends = backends::all();
 
     let cmdline = ui::cmdline(&backends);
+    for backend in &mut backends {
+        backend.configure(&cmdline);
+    }
+    let resolve_extends = cmdline.get_flag("resolve-extends");
+    let warnings_json = cmdline.get_one::<PathBuf>("warnings-json").cloned();
+    let deny_warnings = cmdline.get_flag("deny-warnings");
     let BackendSelection { input, outputs } = BackendSelection::from_matches(cmdline, &backends)?;
 
-    let input_file = fs::read_to_string(&input.path).with_context(|| {
-        format!(
-            "error reading input for backend `{}` at path {}",
-            input.backend.name(),
-            input.path.display()
-        )
-    })?;
-    let ir = input
-        .backend
-        .deserialize(&input_file)
-        .with_context(|| format!("could not deserialize `{}`", input.path.display()))?;
+    let input_file = input.backend.read(&input.path)?;
+    let mut warnings = Vec::new();
+    let mut ir = match (input.backend).deserialize_with_warnings(&input_file, &mut warnings) {
+        Ok(ir) => ir,
+        Err(err) => {
+            let context = format!("could not deserialize `{}`", input.path.display());
+            return match err.downcast::<SpannedError>() {
+                // the diagnostic says it all already, no need to repeat it in the error chain
+                Ok(err) => {
+                    let err = err.in_file(&input.path);
+                    let message = err.to_string();
+                    let rendered =
+                        diagnostic::render(Level::Error, &message, &err.span, &input_file);
+                    eprintln!("{rendered}");
+                    Err(anyhow!(context))
+                }
+                Err(err) => Err(err.context(context)),
+            };
+        }
+    };
+    ir.set_file(&input.path);
+    for warning in &mut warnings {
+        if !warning.span.is_unknown() {
+            warning.span.file = Some(input.path.clone());
+        }
+    }
+    if ir.scope.is_none() {
+        ir.scope = input.backend.scope_from_path(&input.path);
+    }
+    if resolve_extends {
+        let dir = (input.path.parent())
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        UltiSnips::default().resolve_extends(&mut ir, dir)?;
+    }
+
+    if deny_warnings {
+        // a dry run first, so that nothing is written if anything would be lost
+        for backend in outputs.mapping.values() {
+            backend
+                .serialize_with_warnings(&ir, &mut warnings)
+                .with_context(|| format!("could not serialize as `{}`", backend.name()))?;
+        }
+        if !warnings.is_empty() {
+            report(&warnings, &input, &input_file, warnings_json.as_deref())?;
+            bail!(
+                "nothing was written, as {} warning(s) came up and --deny-warnings is set",
+                warnings.len()
+            );
+        }
+    }
 
+    let mut ports = Vec::new();
     for (path, backend) in outputs.mapping {
-        let repr = backend
-            .serialize(&ir)
-            .with_context(|| format!("could not serialize `{}`", path.display()))?;
-        fs::write(&path, repr).with_context(|| {
-            format!(
-                "error writing output for backend `{}` at path {}",
-                backend.name(),
-                path.display()
-            )
-        })?;
+        backend.write(&path, &ir, &mut warnings)?;
+        ports.push((backend.name(), manual_ports(&ir, backend)));
+    }
+
+    report(&warnings, &input, &input_file, warnings_json.as_deref())?;
+
+    for (backend, ports) in ports.into_iter().filter(|(_, ports)| !ports.is_empty()) {
+        eprintln!("note: these snippets contain code `{backend}` can't run, port them by hand:");
+        for (snippet, languages) in ports {
+            let languages = languages.iter().join(", ");
+            if snippet.span.is_unknown() {
+                eprintln!("  - `{}` ({languages})", snippet.trigger);
+            } else {
+                eprintln!(
+                    "  - `{}` at {} ({languages})",
+                    snippet.trigger, snippet.span
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the warnings, underlining the snippets they're about in `input_file` where possible,
+/// and writes them to `json` if given.
+fn report(
+    warnings: &[Warning],
+    input: &Source,
+    input_file: &str,
+    json: Option<&Path>,
+) -> Result<()> {
+    for warning in warnings {
+        // spans coming from the IR backend might point into whatever file it was made from
+        let in_input = warning.span.file.as_deref() == Some(&input.path);
+        eprintln!("{}", warning.render(in_input.then_some(input_file)));
     }
 
+    if let Some(path) = json {
+        let json = serde_json::to_string_pretty(warnings)?;
+        fs::write(path, json)
+            .with_context(|| format!("error writing warnings to {}", path.display()))?;
+    }
     Ok(())
 }
 
-#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+/// Converts snippets from one format into another, without touching the file system.
+///
+/// Only looks at the input itself, so formats storing the scope in the file name lose it. Use
+/// [`Backend::deserialize`], [`Backend::scope_from_path`] and
+/// [`Backend::serialize_with_warnings`] separately if that matters.
+pub fn convert(input: &str, from: &dyn Backend, to: &dyn Backend) -> Result<Converted> {
+    let mut warnings = Vec::new();
+    let ir = from
+        .deserialize_with_warnings(input, &mut warnings)
+        .with_context(|| format!("could not deserialize as `{}`", from.name()))?;
+
+    let output = to
+        .serialize_with_warnings(&ir, &mut warnings)
+        .with_context(|| format!("could not serialize as `{}`", to.name()))?;
+    Ok(Converted { output, warnings })
+}
+
+/// Lists every snippet with code `to` can't run, along with the languages of that code.
+/// Serializing leaves such code out, so these snippets need to be ported by hand.
+pub fn manual_ports<'a>(
+    snippets: &'a SnippetFile,
+    to: &dyn Backend,
+) -> Vec<(&'a Snippet, Vec<Language>)> {
+    snippets
+        .iter()
+        .filter_map(|snippet| {
+            let foreign: Vec<_> = (snippet.body.languages().into_iter())
+                .filter(|language| !to.languages().contains(language))
+                .collect();
+            (!foreign.is_empty()).then_some((snippet, foreign))
+        })
+        .collect()
+}
+
+/// What [`convert`] returns.
+#[derive(Clone, Debug)]
+pub struct Converted {
+    pub output: String,
+    /// Everything that didn't make it into the output exactly.
+    pub warnings: Vec<Warning>,
+}
+
+/// A set of snippets, as read from or written to one file. _The IR_ every backend reads into
+/// and writes from.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
 pub struct SnippetFile {
+    /// Filetype or language all snippets apply to, if known. [`None`] means everywhere.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
+    /// Comments at the top of the file, not belonging to any snippet in particular.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    header: Vec<Trivia>,
     snippets: Vec<Snippet>,
+    /// Comments after the last snippet.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    footer: Vec<Trivia>,
+    /// Filetypes whose snippets apply here as well, like `extends` in UltiSnips.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    extends: Vec<String>,
+    /// Code shared by all snippets of the file.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    globals: Vec<Global>,
+    /// Snippets from elsewhere this file removes, like `clearsnippets` in UltiSnips.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    clears: Vec<Clear>,
+}
+
+impl SnippetFile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn scope(&self) -> Option<&str> {
+        self.scope.as_deref()
+    }
+
+    pub fn set_scope(&mut self, scope: Option<String>) {
+        self.scope = scope;
+    }
+
+    pub fn extends(&self) -> &[String] {
+        &self.extends
+    }
+
+    pub fn extends_mut(&mut self) -> &mut Vec<String> {
+        &mut self.extends
+    }
+
+    pub fn globals(&self) -> &[Global] {
+        &self.globals
+    }
+
+    pub fn globals_mut(&mut self) -> &mut Vec<Global> {
+        &mut self.globals
+    }
+
+    pub fn clears(&self) -> &[Clear] {
+        &self.clears
+    }
+
+    pub fn clears_mut(&mut self) -> &mut Vec<Clear> {
+        &mut self.clears
+    }
+
+    pub fn header(&self) -> &[Trivia] {
+        &self.header
+    }
+
+    pub fn header_mut(&mut self) -> &mut Vec<Trivia> {
+        &mut self.header
+    }
+
+    pub fn footer(&self) -> &[Trivia] {
+        &self.footer
+    }
+
+    pub fn footer_mut(&mut self) -> &mut Vec<Trivia> {
+        &mut self.footer
+    }
+
+    pub fn snippets(&self) -> &[Snippet] {
+        &self.snippets
+    }
+
+    /// For anything the other helpers don't cover, like inserting or sorting.
+    pub fn snippets_mut(&mut self) -> &mut Vec<Snippet> {
+        &mut self.snippets
+    }
+
+    pub fn push(&mut self, snippet: Snippet) {
+        self.snippets.push(snippet);
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, Snippet> {
+        self.snippets.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> slice::IterMut<'_, Snippet> {
+        self.snippets.iter_mut()
+    }
+
+    /// Keeps only the snippets for which `keep` returns `true`.
+    pub fn retain(&mut self, keep: impl FnMut(&Snippet) -> bool) {
+        self.snippets.retain(keep);
+    }
+
+    pub fn len(&self) -> usize {
+        self.snippets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snippets.is_empty()
+    }
+
+    /// Records `path` as the file all snippets without one in their span came from.
+    pub fn set_file(&mut self, path: &Path) {
+        let spans = (self.snippets.iter_mut().map(|snippet| &mut snippet.span))
+            .chain(self.globals.iter_mut().map(|global| &mut global.span));
+        for span in spans {
+            if !span.is_unknown() {
+                span.file.get_or_insert_with(|| path.to_path_buf());
+            }
+        }
+    }
+
+    /// Forgets where all snippets and globals came from, so files can be compared by what's in
+    /// them alone.
+    pub fn clear_spans(&mut self) {
+        let spans = (self.snippets.iter_mut().map(|snippet| &mut snippet.span))
+            .chain(self.globals.iter_mut().map(|global| &mut global.span));
+        for span in spans {
+            *span = Span::default();
+        }
+    }
+
+    /// The effective scopes of all snippets, see [`SnippetFile::split_by_scope`].
+    pub(crate) fn scopes(&self) -> BTreeSet<Option<&str>> {
+        (self.snippets.iter())
+            .map(|snippet| snippet.scope().or(self.scope()))
+            .collect()
+    }
+
+    /// Groups the snippets by their effective scope, which is the one of the snippet itself,
+    /// falling back to the one of the file. Each group has its scope as file scope.
+    pub fn split_by_scope(&self) -> BTreeMap<Option<String>, SnippetFile> {
+        let mut groups: BTreeMap<_, SnippetFile> = BTreeMap::new();
+
+        for snippet in &self.snippets {
+            let scope = snippet.scope.as_ref().or(self.scope.as_ref()).cloned();
+            // extends and clears are only meant for the filetype of the file itself
+            let own = scope == self.scope;
+            groups
+                .entry(scope.clone())
+                .or_insert_with(|| SnippetFile {
+                    extends: if own {
+                        self.extends.clone()
+                    } else {
+                        Vec::new()
+                    },
+                    clears: if own { self.clears.clone() } else { Vec::new() },
+                    scope,
+                    header: self.header.clone(),
+                    // the snippets might need them wherever they end up
+                    globals: self.globals.clone(),
+                    snippets: Vec::new(),
+                    footer: self.footer.clone(),
+                })
+                .snippets
+                .push(snippet.clone());
+        }
+
+        groups
+    }
+}
+
+impl IntoIterator for SnippetFile {
+    type Item = Snippet;
+    type IntoIter = vec::IntoIter<Snippet>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.snippets.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a SnippetFile {
+    type Item = &'a Snippet;
+    type IntoIter = slice::Iter<'a, Snippet>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut SnippetFile {
+    type Item = &'a mut Snippet;
+    type IntoIter = slice::IterMut<'a, Snippet>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl FromIterator<Snippet> for SnippetFile {
+    fn from_iter<I: IntoIterator<Item = Snippet>>(iter: I) -> Self {
+        Self {
+            snippets: iter.into_iter().collect(),
+            ..Self::default()
+        }
+    }
 }
 
-#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+impl Extend<Snippet> for SnippetFile {
+    fn extend<I: IntoIterator<Item = Snippet>>(&mut self, iter: I) {
+        self.snippets.extend(iter);
+    }
+}
+
+/// Removes snippets other files defined for the same filetype with a lower priority, so this
+/// file can replace them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct Clear {
+    /// Triggers of the snippets to remove. Empty means all of them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub triggers: Vec<String>,
+    /// Only snippets with a lower priority than this are removed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i64>,
+}
+
+impl Clear {
+    /// Whether this removes `snippet`, assuming it's from another file.
+    pub fn removes(&self, snippet: &Snippet) -> bool {
+        let matches = self.triggers.is_empty() || self.triggers.contains(&snippet.trigger);
+        matches && snippet.priority.unwrap_or(0) < self.priority.unwrap_or(0)
+    }
+}
+
+/// Code run once for a whole file, usually defining helpers its snippets use, like
+/// `global !p` in UltiSnips.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct Global {
+    pub language: Language,
+    pub source: String,
+    /// Comments and blank lines directly above the code.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub leading: Vec<Trivia>,
+    /// Where the code was read from, if it was read at all.
+    #[serde(default, skip_serializing_if = "Span::is_unknown")]
+    pub span: Span,
+}
+
+/// A single snippet. Built using [`Snippet::new`] and the `with_*` methods, which cover
+/// everything but the [`Span`], which only deserializers know.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
 pub struct Snippet {
     trigger: String,
-    replacement: String,
+    trigger_kind: TriggerKind,
+    body: Body,
 
-    #[serde(skip_serializing_if = "Option::is_none")]
-    options: Option<String>,
+    #[serde(default, skip_serializing_if = "Options::is_empty")]
+    options: Options,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    contexts: Vec<context::Context>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    actions: Vec<Action>,
     #[serde(skip_serializing_if = "Option::is_none")]
     description: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     priority: Option<i64>,
+    /// Overrides the scope of the containing [`SnippetFile`] for only this snippet.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    scope: Option<String>,
+
+    /// Comments and blank lines directly above this snippet.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    leading: Vec<Trivia>,
+    /// Comments on the same line after this snippet.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    trailing: Vec<Trivia>,
+
+    /// Data only one backend understands, keyed by that backend's name. Only written back by
+    /// the same backend, but kept through conversions so it isn't lost either.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    extra: BTreeMap<String, Extra>,
+
+    /// Where this snippet was read from, if it was read at all.
+    #[serde(default, skip_serializing_if = "Span::is_unknown")]
+    span: Span,
+}
+
+impl Snippet {
+    /// A snippet expanding `trigger` to `body` everywhere, with no options.
+    pub fn new(trigger: impl Into<String>, body: Body) -> Self {
+        Self {
+            trigger: trigger.into(),
+            body,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_trigger_kind(mut self, kind: TriggerKind) -> Self {
+        self.trigger_kind = kind;
+        self
+    }
+
+    pub fn with_flag(mut self, flag: Flag) -> Self {
+        self.options.flags.insert(flag);
+        self
+    }
+
+    pub fn with_options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Adds a context, the snippet then expands if any of them holds.
+    pub fn with_context(mut self, context: context::Context) -> Self {
+        self.contexts.push(context);
+        self
+    }
+
+    pub fn with_action(mut self, action: Action) -> Self {
+        self.actions.push(action);
+        self
+    }
+
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    pub fn with_priority(mut self, priority: i64) -> Self {
+        self.priority = Some(priority);
+        self
+    }
+
+    pub fn with_scope(mut self, scope: impl Into<String>) -> Self {
+        self.scope = Some(scope.into());
+        self
+    }
+
+    pub fn with_leading(mut self, leading: Vec<Trivia>) -> Self {
+        self.leading = leading;
+        self
+    }
+
+    pub fn with_trailing(mut self, trailing: Vec<Trivia>) -> Self {
+        self.trailing = trailing;
+        self
+    }
+
+    /// Sets the extra data of the given backend, see [`Snippet::extra`].
+    pub fn with_extra(mut self, backend: impl Into<String>, extra: Extra) -> Self {
+        self.extra.insert(backend.into(), extra);
+        self
+    }
+
+    pub fn trigger(&self) -> &str {
+        &self.trigger
+    }
+
+    pub fn set_trigger(&mut self, trigger: impl Into<String>) {
+        self.trigger = trigger.into();
+    }
+
+    pub fn trigger_kind(&self) -> TriggerKind {
+        self.trigger_kind
+    }
+
+    pub fn set_trigger_kind(&mut self, kind: TriggerKind) {
+        self.trigger_kind = kind;
+    }
+
+    pub fn body(&self) -> &Body {
+        &self.body
+    }
+
+    pub fn body_mut(&mut self) -> &mut Body {
+        &mut self.body
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn options_mut(&mut self) -> &mut Options {
+        &mut self.options
+    }
+
+    pub fn contexts(&self) -> &[context::Context] {
+        &self.contexts
+    }
+
+    pub fn contexts_mut(&mut self) -> &mut Vec<context::Context> {
+        &mut self.contexts
+    }
+
+    pub fn actions(&self) -> &[Action] {
+        &self.actions
+    }
+
+    pub fn actions_mut(&mut self) -> &mut Vec<Action> {
+        &mut self.actions
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn set_description(&mut self, description: Option<String>) {
+        self.description = description;
+    }
+
+    pub fn priority(&self) -> Option<i64> {
+        self.priority
+    }
+
+    pub fn set_priority(&mut self, priority: Option<i64>) {
+        self.priority = priority;
+    }
+
+    /// The scope of only this snippet. Falls back to the one of the [`SnippetFile`] if
+    /// [`None`].
+    pub fn scope(&self) -> Option<&str> {
+        self.scope.as_deref()
+    }
+
+    pub fn set_scope(&mut self, scope: Option<String>) {
+        self.scope = scope;
+    }
+
+    pub fn leading(&self) -> &[Trivia] {
+        &self.leading
+    }
+
+    pub fn leading_mut(&mut self) -> &mut Vec<Trivia> {
+        &mut self.leading
+    }
+
+    pub fn trailing(&self) -> &[Trivia] {
+        &self.trailing
+    }
+
+    pub fn trailing_mut(&mut self) -> &mut Vec<Trivia> {
+        &mut self.trailing
+    }
+
+    /// Data only the named backend understands, like keys of an OLS entry this version doesn't
+    /// know about. Keyed by backend name.
+    pub fn extra(&self) -> &BTreeMap<String, Extra> {
+        &self.extra
+    }
+
+    pub fn extra_mut(&mut self) -> &mut BTreeMap<String, Extra> {
+        &mut self.extra
+    }
+
+    /// Where this snippet was read from. [`Span::is_unknown`] if it was built by hand.
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
 }
 
+/// Whatever a backend wants to keep about a snippet without the IR knowing about it, by field
+/// name.
+pub type Extra = BTreeMap<String, serde_json::Value>;
+
 #[derive(Debug)]
 pub struct BackendSelection<'backends> {
     pub input: Source<'backends>,
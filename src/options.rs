@@ -0,0 +1,197 @@
+//! Snippet options, and how each backend spells them
+//!
+//! Most formats pack options into a string of single letters, but the same letter can mean
+//! completely different things depending on the format (UltiSnips `t` keeps tabs, OLS `t`
+//! restricts to text mode). So each backend describes its letters in an [`OptionTable`], which
+//! also documents why a [`Flag`] can't be expressed, if it can't.
+//!
+//! Letters deciding the [`TriggerKind`](crate::trigger::TriggerKind) or a
+//! [`Context`](crate::context::Context) are not flags, backends take them out of the string
+//! before handing the rest to their table.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{diagnostic::Warning, Snippet};
+
+/// A set of [`Flag`]s, plus letters that weren't understood.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct Options {
+    pub flags: BTreeSet<Flag>,
+    /// Letters the [`OptionTable`] of the reading backend didn't know, keyed by that backend's
+    /// name. Only written back by the same backend, since in another format they could mean
+    /// anything.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub unknown: BTreeMap<String, String>,
+}
+
+impl Options {
+    pub fn has(&self, flag: Flag) -> bool {
+        self.flags.contains(&flag)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.flags.is_empty() && self.unknown.is_empty()
+    }
+}
+
+/// A single option with the same meaning across all backends.
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum Flag {
+    /// Expands as soon as the trigger is typed, without pressing the expand key.
+    AutoExpand,
+    /// Only expands if the trigger is the first non-whitespace on the line.
+    BeginningOfLine,
+    /// Only expands if there's a selection, which replaces the trigger.
+    Visual,
+    /// Inserts tabs in the body as-is instead of expanding them according to the editor.
+    KeepTabs,
+    /// Removes trailing whitespace of the current line before jumping to the next tabstop.
+    TrimBeforeJump,
+    /// Removes trailing whitespace of every line of the body after expanding.
+    TrimWhitespace,
+}
+
+impl Flag {
+    pub const ALL: [Flag; 6] = [
+        Flag::AutoExpand,
+        Flag::BeginningOfLine,
+        Flag::Visual,
+        Flag::KeepTabs,
+        Flag::TrimBeforeJump,
+        Flag::TrimWhitespace,
+    ];
+}
+
+impl fmt::Display for Flag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Flag::AutoExpand => "auto-expand",
+            Flag::BeginningOfLine => "beginning-of-line",
+            Flag::Visual => "visual",
+            Flag::KeepTabs => "keep-tabs",
+            Flag::TrimBeforeJump => "trim-before-jump",
+            Flag::TrimWhitespace => "trim-whitespace",
+        })
+    }
+}
+
+/// How a backend handles a [`Flag`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Support {
+    /// Spelled using this letter.
+    Letter(char),
+    /// Can't be expressed, with a human-readable reason why.
+    Unsupported(&'static str),
+}
+
+/// Describes for every [`Flag`] how one backend handles it.
+#[derive(Debug)]
+pub struct OptionTable {
+    /// Name of the backend, as in [`Backend::name`](crate::backends::Backend::name).
+    pub backend: &'static str,
+    pub entries: &'static [(Flag, Support)],
+}
+
+impl OptionTable {
+    pub fn support(&self, flag: Flag) -> Option<Support> {
+        self.entries
+            .iter()
+            .find(|(candidate, _)| *candidate == flag)
+            .map(|(_, support)| *support)
+    }
+
+    /// Parses the given letters. Unknown ones are kept in [`Options::unknown`].
+    pub fn parse(&self, letters: &str) -> Options {
+        let mut options = Options::default();
+        let mut unknown = String::new();
+
+        for letter in letters.chars() {
+            let flag = self
+                .entries
+                .iter()
+                .find_map(|(flag, support)| (*support == Support::Letter(letter)).then_some(*flag));
+
+            match flag {
+                Some(flag) => {
+                    options.flags.insert(flag);
+                }
+                None => unknown.push(letter),
+            }
+        }
+
+        if !unknown.is_empty() {
+            options.unknown.insert(self.backend.to_string(), unknown);
+        }
+        options
+    }
+
+    /// Returns the letters for all supported flags, followed by the unknown letters that
+    /// originally came from this backend. Unsupported flags are skipped.
+    pub fn letters(&self, options: &Options) -> String {
+        let mut letters: String = self
+            .entries
+            .iter()
+            .filter_map(|(flag, support)| match support {
+                Support::Letter(letter) if options.has(*flag) => Some(*letter),
+                _ => None,
+            })
+            .collect();
+
+        if let Some(unknown) = options.unknown.get(self.backend) {
+            letters.push_str(unknown);
+        }
+        letters
+    }
+
+    /// Warns about the options of `snippet` that [`OptionTable::letters`] skips.
+    pub fn warn_skipped(&self, snippet: &Snippet, warnings: &mut Vec<Warning>) {
+        for (flag, support) in self.entries {
+            if let Support::Unsupported(reason) = support {
+                if snippet.options.has(*flag) {
+                    let message = format!("the {flag} option was left out");
+                    warnings.push(Warning::new(snippet, message, *reason));
+                }
+            }
+        }
+
+        for (backend, letters) in &snippet.options.unknown {
+            if backend != self.backend {
+                warnings.push(Warning::new(
+                    snippet,
+                    format!("the option letters `{letters}` from `{backend}` were left out"),
+                    format!("they could mean anything in `{}`", self.backend),
+                ));
+            }
+        }
+    }
+
+    /// Warns about the letters [`OptionTable::parse`] didn't understand for `snippet`.
+    pub fn warn_unknown(&self, snippet: &Snippet, warnings: &mut Vec<Warning>) {
+        if let Some(letters) = snippet.options.unknown.get(self.backend) {
+            warnings.push(Warning::new(
+                snippet,
+                format!(
+                    "the option letters `{letters}` are only kept for `{}`",
+                    self.backend
+                ),
+                "their meaning is unknown",
+            ));
+        }
+    }
+}
+
+/// Removes all occurrences of `letter` from `options`, returning if there were any.
+pub(crate) fn take_letter(options: &mut String, letter: char) -> bool {
+    let before = options.len();
+    options.retain(|ch| ch != letter);
+    options.len() != before
+}
@@ -0,0 +1,580 @@
+//! Regex triggers as JavaScript and Python see them, and translating between the two
+//!
+//! OLS matches triggers using JavaScript regexes, UltiSnips using Python's `re`. Most of the
+//! syntax is the same, the rest is rewritten by [`translate`], like named groups being
+//! `(?<name>...)` in one and `(?P<name>...)` in the other. Constructs only one of them has are
+//! an error, since a trigger that silently matches something else is worse than none.
+//!
+//! This isn't a full regex parser, it only looks as closely as needed to find those
+//! constructs. Broken regexes stay broken.
+
+use std::{borrow::Cow, fmt};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::trigger::RegexFlags;
+
+/// The regex engine a trigger is meant for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Dialect {
+    JavaScript,
+    Python,
+}
+
+impl fmt::Display for Dialect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Dialect::JavaScript => "JavaScript",
+            Dialect::Python => "Python",
+        })
+    }
+}
+
+/// A part of a regex that has no equivalent in the other dialect.
+#[derive(Debug, Error)]
+#[error("{to} has no equivalent for `{construct}`, {reason}")]
+pub struct TranslateError {
+    pub construct: String,
+    pub to: Dialect,
+    pub reason: &'static str,
+}
+
+/// Rewrites `pattern`, written for the dialect in `flags`, so it matches the same in `to`.
+/// Patterns of an unknown dialect are taken as-is.
+pub fn translate<'a>(
+    pattern: &'a str,
+    flags: RegexFlags,
+    to: Dialect,
+) -> Result<Cow<'a, str>, TranslateError> {
+    let from = match flags.dialect {
+        Some(from) if from != to => from,
+        _ => return Ok(Cow::Borrowed(pattern)),
+    };
+
+    let tokens = tokenize(pattern, from);
+    let mut output = String::with_capacity(pattern.len());
+    let mut i = 0;
+    while i < tokens.len() {
+        let (kind, text) = tokens[i];
+
+        // ASCII-only classes, as `to_python` writes them, are what JavaScript has anyway
+        if let [(Kind::Open, "(?a:"), (_, class), (Kind::Close, _), ..] = tokens[i..] {
+            if to == Dialect::JavaScript && ASCII_ONLY.contains(&class) {
+                output.push_str(class);
+                i += 3;
+                continue;
+            }
+        }
+
+        let translated = match to {
+            Dialect::Python => to_python(kind, text, &tokens[i + 1..])?,
+            Dialect::JavaScript => to_javascript(kind, text, flags)?,
+        };
+        output.push_str(&translated);
+        i += 1;
+    }
+
+    Ok(Cow::Owned(output))
+}
+
+/// Escapes which only look at ASCII in JavaScript, but at all of Unicode in Python.
+const ASCII_ONLY: [&str; 6] = [r"\b", r"\B", r"\d", r"\D", r"\w", r"\W"];
+
+/// What `\s` matches in JavaScript, as contents of a Python character class. Python's own
+/// `\s` differs in a few control characters.
+const JAVASCRIPT_WHITESPACE: &str =
+    r"\t\n\v\f\r \u00a0\u1680\u2000-\u200a\u2028\u2029\u202f\u205f\u3000\ufeff";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Kind {
+    /// Matches exactly one character, like `a`, `\d` or `.`.
+    Char,
+    /// `[...]`
+    Class,
+    /// Matches no character, like `^` or `\b`.
+    Assertion,
+    /// `\1`, `\k<name>` or `(?P=name)`.
+    Backref,
+    /// The start of any kind of group, up to its contents.
+    Open,
+    Close,
+    Alternation,
+    /// `*`, `{2,3}` and so on, including a following `?` or `+`.
+    Quantifier,
+    /// `(?i)`
+    Flags,
+    /// `(?#...)`
+    Comment,
+}
+
+type Token<'a> = (Kind, &'a str);
+
+fn tokenize(pattern: &str, from: Dialect) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = pattern;
+
+    while let Some(ch) = rest.chars().next() {
+        let (kind, len) = match ch {
+            '\\' => escape(rest, from),
+            '[' => (Kind::Class, class_len(rest, from)),
+            '(' => group(rest, from),
+            ')' => (Kind::Close, 1),
+            '|' => (Kind::Alternation, 1),
+            '^' | '$' => (Kind::Assertion, 1),
+            '*' | '+' | '?' => (Kind::Quantifier, quantifier_suffix(rest, 1, from)),
+            '{' => match repeat_len(rest, from) {
+                Some(len) => (Kind::Quantifier, quantifier_suffix(rest, len, from)),
+                None => (Kind::Char, 1),
+            },
+            _ => (Kind::Char, ch.len_utf8()),
+        };
+
+        tokens.push((kind, &rest[..len]));
+        rest = &rest[len..];
+    }
+
+    tokens
+}
+
+/// Reads the escape at the start of `input`, returning what it is and how many bytes it spans.
+fn escape(input: &str, from: Dialect) -> (Kind, usize) {
+    let Some(ch) = input[1..].chars().next() else {
+        return (Kind::Char, 1);
+    };
+    let len = 1 + ch.len_utf8();
+    let after = &input[len..];
+    let braced = || len + after.find('}').map_or(after.len(), |end| end + 1);
+    let count =
+        |pred: fn(&char) -> bool, max| len + after.chars().take(max).take_while(pred).count();
+
+    let python = from == Dialect::Python;
+    match ch {
+        'k' if !python && after.starts_with('<') => (
+            Kind::Backref,
+            len + after.find('>').map_or(after.len(), |end| end + 1),
+        ),
+        '1'..='9' => (Kind::Backref, count(char::is_ascii_digit, usize::MAX)),
+        '0' => (Kind::Char, count(char::is_ascii_digit, 2)),
+        'b' | 'B' => (Kind::Assertion, len),
+        'A' | 'Z' if python => (Kind::Assertion, len),
+        'x' => (Kind::Char, count(char::is_ascii_hexdigit, 2)),
+        'u' if after.starts_with('{') => (Kind::Char, braced()),
+        'u' => (Kind::Char, count(char::is_ascii_hexdigit, 4)),
+        'U' if python => (Kind::Char, count(char::is_ascii_hexdigit, 8)),
+        'N' if python && after.starts_with('{') => (Kind::Char, braced()),
+        'p' | 'P' if !python && after.starts_with('{') => (Kind::Char, braced()),
+        'c' if !python && after.starts_with(|ch: char| ch.is_ascii_alphabetic()) => {
+            (Kind::Char, len + 1)
+        }
+        _ => (Kind::Char, len),
+    }
+}
+
+/// Returns how many bytes the character class at the start of `input` spans.
+fn class_len(input: &str, from: Dialect) -> usize {
+    let mut len = 1;
+    if input[len..].starts_with('^') {
+        len += 1;
+    }
+    // Python takes a `]` right at the start literally, JavaScript as end of an empty class
+    if from == Dialect::Python && input[len..].starts_with(']') {
+        len += 1;
+    }
+
+    while let Some(ch) = input[len..].chars().next() {
+        len += match ch {
+            '\\' => escape(&input[len..], from).1,
+            ']' => return len + 1,
+            _ => ch.len_utf8(),
+        };
+    }
+    len
+}
+
+/// Reads the start of the group at the start of `input`, returning what it is and how many
+/// bytes it spans.
+fn group(input: &str, from: Dialect) -> (Kind, usize) {
+    let Some(after) = input.strip_prefix("(?") else {
+        return (Kind::Open, 1);
+    };
+    let up_to = |end| 2 + after.find(end).map_or(after.len(), |pos| pos + 1);
+
+    if from == Dialect::Python {
+        if after.starts_with('#') {
+            return (Kind::Comment, up_to(')'));
+        }
+        if after.starts_with("P=") {
+            return (Kind::Backref, up_to(')'));
+        }
+        if after.starts_with("P<") {
+            return (Kind::Open, up_to('>'));
+        }
+    }
+
+    if after.starts_with("<=") || after.starts_with("<!") {
+        return (Kind::Open, 4);
+    }
+    if after.starts_with('<') {
+        return (Kind::Open, up_to('>'));
+    }
+    if after.starts_with([':', '=', '!', '>', '(']) {
+        return (Kind::Open, 3);
+    }
+
+    let letters = after
+        .find(|ch: char| !ch.is_ascii_alphabetic() && ch != '-')
+        .unwrap_or(after.len());
+    match after[letters..].chars().next() {
+        Some(')') => (Kind::Flags, 2 + letters + 1),
+        Some(':') => (Kind::Open, 2 + letters + 1),
+        _ => (Kind::Open, 2),
+    }
+}
+
+/// Returns how many bytes the `{n,m}` at the start of `input` spans, if it is one. Otherwise
+/// the brace is just a brace.
+fn repeat_len(input: &str, from: Dialect) -> Option<usize> {
+    let end = input.find('}')?;
+    let inner = &input[1..end];
+    let (min, max) = inner.split_once(',').unwrap_or((inner, ""));
+
+    let digits = |part: &str| part.chars().all(|ch| ch.is_ascii_digit());
+    // `{,3}` is `{0,3}` in Python, but just text in JavaScript
+    let has_min = !min.is_empty() || (from == Dialect::Python && !max.is_empty());
+    (has_min && digits(min) && digits(max)).then_some(end + 1)
+}
+
+/// Extends a quantifier spanning `len` bytes by a lazy `?`, or a possessive `+` in Python.
+fn quantifier_suffix(input: &str, len: usize, from: Dialect) -> usize {
+    match input[len..].chars().next() {
+        Some('?') => len + 1,
+        Some('+') if from == Dialect::Python => len + 1,
+        _ => len,
+    }
+}
+
+fn untranslatable(construct: &str, to: Dialect, reason: &'static str) -> TranslateError {
+    TranslateError {
+        construct: construct.to_string(),
+        to,
+        reason,
+    }
+}
+
+/// Translates one token from JavaScript to Python. `following` is everything after it.
+fn to_python<'a>(
+    kind: Kind,
+    text: &'a str,
+    following: &[Token<'_>],
+) -> Result<Cow<'a, str>, TranslateError> {
+    let translated = match kind {
+        Kind::Char => return escape_to_python(text, false),
+        Kind::Class => match text {
+            // JavaScript specialties, matching anything and nothing respectively
+            "[^]" => Cow::Borrowed(r"[\s\S]"),
+            "[]" => Cow::Borrowed("(?!)"),
+            _ => Cow::Owned(map_class(text, Dialect::JavaScript, |text| {
+                escape_to_python(text, true)
+            })?),
+        },
+        Kind::Assertion if ASCII_ONLY.contains(&text) => Cow::Owned(format!("(?a:{text})")),
+        Kind::Open if text.starts_with("(?<=") || text.starts_with("(?<!") => {
+            let end = matching_close(following);
+            if width(&following[..end]).is_none() {
+                let construct: String = following[..end].iter().map(|(_, text)| *text).collect();
+                return Err(untranslatable(
+                    &format!("{text}{construct})"),
+                    Dialect::Python,
+                    "its lookbehinds need to match a fixed number of characters",
+                ));
+            }
+            Cow::Borrowed(text)
+        }
+        Kind::Open if text.starts_with("(?<") && text.ends_with('>') => {
+            let name = &text["(?<".len()..text.len() - 1];
+            let valid = name.starts_with(|ch: char| ch.is_ascii_alphabetic() || ch == '_')
+                && name
+                    .chars()
+                    .all(|ch| ch.is_ascii_alphanumeric() || ch == '_');
+            if !valid {
+                return Err(untranslatable(
+                    text,
+                    Dialect::Python,
+                    "its group names need to be identifiers",
+                ));
+            }
+            Cow::Owned(format!("(?P<{name}>"))
+        }
+        Kind::Backref if text.starts_with(r"\k<") && text.ends_with('>') => {
+            Cow::Owned(format!("(?P={})", &text[r"\k<".len()..text.len() - 1]))
+        }
+        _ => Cow::Borrowed(text),
+    };
+
+    Ok(translated)
+}
+
+/// Translates one escape, `in_class` says if it's inside a character class.
+fn escape_to_python(text: &str, in_class: bool) -> Result<Cow<'_, str>, TranslateError> {
+    let Some(escaped) = text.strip_prefix('\\') else {
+        return Ok(escape_brace(text));
+    };
+
+    let translated = match escaped.chars().next() {
+        Some('d') if in_class => Cow::Borrowed("0-9"),
+        Some('w') if in_class => Cow::Borrowed("a-zA-Z0-9_"),
+        Some('s') if in_class => Cow::Borrowed(JAVASCRIPT_WHITESPACE),
+        Some('D' | 'W' | 'S') if in_class => {
+            return Err(untranslatable(
+                text,
+                Dialect::Python,
+                "its version also matches non-ASCII, and can't be narrowed inside a class",
+            ))
+        }
+        _ if ASCII_ONLY.contains(&text) => Cow::Owned(format!("(?a:{text})")),
+        Some('s') => Cow::Owned(format!("[{JAVASCRIPT_WHITESPACE}]")),
+        Some('S') => Cow::Owned(format!("[^{JAVASCRIPT_WHITESPACE}]")),
+        Some('p' | 'P') if escaped.len() > 1 => {
+            return Err(untranslatable(
+                text,
+                Dialect::Python,
+                "its `re` module has no Unicode property classes",
+            ))
+        }
+        Some('u') if escaped.starts_with("u{") => {
+            match u32::from_str_radix(escaped[2..].trim_end_matches('}'), 16) {
+                Ok(code) => Cow::Owned(format!(r"\U{code:08x}")),
+                Err(_) => Cow::Borrowed(text),
+            }
+        }
+        Some('c') if escaped.len() == 2 => {
+            Cow::Owned(format!(r"\x{:02x}", escaped.as_bytes()[1] % 32))
+        }
+        // JavaScript takes unknown escaped letters literally, Python rejects or interprets them
+        Some(ch) if ch.is_ascii_alphabetic() && !"bBdDwWsSfnrtvxu".contains(ch) => {
+            Cow::Borrowed(escaped)
+        }
+        _ => Cow::Borrowed(text),
+    };
+
+    Ok(translated)
+}
+
+/// Translates one token from Python to JavaScript.
+fn to_javascript(
+    kind: Kind,
+    text: &str,
+    flags: RegexFlags,
+) -> Result<Cow<'_, str>, TranslateError> {
+    let error = |reason| Err(untranslatable(text, Dialect::JavaScript, reason));
+
+    let translated = match kind {
+        Kind::Char => return escape_to_javascript(text, flags),
+        Kind::Class => Cow::Owned(map_class(text, Dialect::Python, |text| {
+            escape_to_javascript(text, flags)
+        })?),
+        Kind::Assertion if text == r"\A" || text == r"\Z" => {
+            if flags.multi_line {
+                return error("its `^` and `$` match at every line with the `m` flag");
+            }
+            Cow::Borrowed(if text == r"\A" { "^" } else { "$" })
+        }
+        Kind::Open if text.starts_with("(?P<") => Cow::Owned(format!("(?<{}", &text[4..])),
+        Kind::Open if text == "(?>" => return error("it has no atomic groups"),
+        Kind::Open if text == "(?(" => return error("it has no conditional groups"),
+        Kind::Open if text.ends_with(':') && text.len() > "(?:".len() => {
+            return error("its flags can only apply to the whole pattern")
+        }
+        Kind::Flags => {
+            return error("its flags can only apply to the whole pattern, and are only `imsu`")
+        }
+        Kind::Comment => Cow::Borrowed(""),
+        Kind::Backref if text.starts_with("(?P=") && text.ends_with(')') => {
+            Cow::Owned(format!(r"\k<{}>", &text[4..text.len() - 1]))
+        }
+        Kind::Quantifier if text.len() > 1 && text.ends_with('+') => {
+            return error("it has no possessive quantifiers")
+        }
+        Kind::Quantifier if text.starts_with("{,") => Cow::Owned(format!("{{0{}", &text[1..])),
+        _ => Cow::Borrowed(text),
+    };
+
+    Ok(translated)
+}
+
+fn escape_to_javascript(text: &str, flags: RegexFlags) -> Result<Cow<'_, str>, TranslateError> {
+    let Some(escaped) = text.strip_prefix('\\') else {
+        return Ok(escape_brace(text));
+    };
+
+    let translated = match escaped.chars().next() {
+        Some('N') if escaped.len() > 1 => {
+            return Err(untranslatable(
+                text,
+                Dialect::JavaScript,
+                "it has no escapes for characters by name",
+            ))
+        }
+        Some('U') => match u32::from_str_radix(&escaped[1..], 16) {
+            Ok(code) if flags.unicode => Cow::Owned(format!(r"\u{{{code:x}}}")),
+            Ok(code) if code <= 0xFFFF => Cow::Owned(format!(r"\u{code:04x}")),
+            Ok(_) => {
+                return Err(untranslatable(
+                    text,
+                    Dialect::JavaScript,
+                    "it needs the `u` flag for characters beyond `\\uffff`",
+                ))
+            }
+            Err(_) => Cow::Borrowed(text),
+        },
+        Some('a') => Cow::Borrowed(r"\x07"),
+        _ => Cow::Borrowed(text),
+    };
+
+    Ok(translated)
+}
+
+/// A lone brace is fine in both, except for JavaScript with the `u` flag.
+fn escape_brace(text: &str) -> Cow<'_, str> {
+    match text {
+        "{" => Cow::Borrowed(r"\{"),
+        "}" => Cow::Borrowed(r"\}"),
+        _ => Cow::Borrowed(text),
+    }
+}
+
+/// Translates every escape in the character class `text` using `translate`.
+fn map_class<'a>(
+    text: &'a str,
+    from: Dialect,
+    translate: impl Fn(&'a str) -> Result<Cow<'a, str>, TranslateError>,
+) -> Result<String, TranslateError> {
+    let start = if text.starts_with("[^") { 2 } else { 1 };
+    let mut output = text[..start].to_string();
+    let mut rest = &text[start..];
+
+    // the other one might think it's the end of the class
+    if let Some(after) = rest.strip_prefix(']').filter(|_| from == Dialect::Python) {
+        output.push_str(r"\]");
+        rest = after;
+    }
+
+    while let Some(ch) = rest.chars().next() {
+        let len = match ch {
+            '\\' => escape(rest, from).1,
+            _ => ch.len_utf8(),
+        };
+        if ch == '\\' {
+            output.push_str(&translate(&rest[..len])?);
+        } else {
+            output.push_str(&rest[..len]);
+        }
+        rest = &rest[len..];
+    }
+
+    Ok(output)
+}
+
+/// Returns the index of the token closing the group `tokens` are the contents of, or their
+/// length if it's never closed.
+fn matching_close(tokens: &[Token<'_>]) -> usize {
+    let mut depth = 0;
+    for (i, (kind, _)) in tokens.iter().enumerate() {
+        match kind {
+            Kind::Open => depth += 1,
+            Kind::Close if depth == 0 => return i,
+            Kind::Close => depth -= 1,
+            _ => (),
+        }
+    }
+    tokens.len()
+}
+
+/// How many characters `tokens` always match, if that's fixed. All alternatives need to match
+/// the same number.
+fn width(tokens: &[Token<'_>]) -> Option<usize> {
+    let mut alternatives = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, (kind, _)) in tokens.iter().enumerate() {
+        match kind {
+            Kind::Open => depth += 1,
+            Kind::Close => depth -= 1,
+            Kind::Alternation if depth == 0 => {
+                alternatives.push(&tokens[start..i]);
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    alternatives.push(&tokens[start..]);
+
+    let widths: Vec<_> = alternatives.into_iter().map(sequence_width).collect();
+    let first = widths[0]?;
+    widths
+        .iter()
+        .all(|width| *width == Some(first))
+        .then_some(first)
+}
+
+fn sequence_width(tokens: &[Token<'_>]) -> Option<usize> {
+    let mut total: usize = 0;
+    let mut last: usize = 0;
+    let mut i = 0;
+
+    while i < tokens.len() {
+        let (kind, text) = tokens[i];
+        i += 1;
+
+        let atom = match kind {
+            Kind::Char | Kind::Class => 1,
+            Kind::Backref => return None,
+            Kind::Open => {
+                let end = i + matching_close(&tokens[i..]);
+                let inner = &tokens[i..end];
+                i = end + 1;
+
+                let lookaround = ["(?=", "(?!", "(?<=", "(?<!"].contains(&text);
+                if lookaround {
+                    0
+                } else {
+                    width(inner)?
+                }
+            }
+            Kind::Quantifier => {
+                let repeated = match exact_repeat(text) {
+                    Some(count) => last.checked_mul(count)?,
+                    // repeating nothing any number of times is still nothing
+                    None if last == 0 => 0,
+                    None => return None,
+                };
+                total = (total - last).checked_add(repeated)?;
+                last = repeated;
+                continue;
+            }
+            _ => 0,
+        };
+
+        total = total.checked_add(atom)?;
+        last = atom;
+    }
+
+    Some(total)
+}
+
+/// Returns `n` for `{n}` and `{n,n}`, the only quantifiers repeating a fixed number of times.
+fn exact_repeat(quantifier: &str) -> Option<usize> {
+    let inner = quantifier
+        .trim_end_matches(['?', '+'])
+        .strip_prefix('{')?
+        .strip_suffix('}')?;
+    match inner.split_once(',') {
+        None => inner.parse().ok(),
+        Some((min, max)) if min == max => min.parse().ok(),
+        Some(_) => None,
+    }
+}
@@ -0,0 +1,154 @@
+//! Where in the input something came from
+
+use std::{
+    error::Error,
+    fmt,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A range in an input file. The default one is all zeroes, meaning the thing wasn't read from
+/// anywhere.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct Span {
+    /// Set once known, deserializers only ever see the contents.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+    pub start: Position,
+    /// Exclusive.
+    pub end: Position,
+}
+
+impl Span {
+    pub fn is_unknown(&self) -> bool {
+        self.start.line == 0
+    }
+
+    /// A span from the start of this one to the end of `other`.
+    pub fn to(&self, other: &Span) -> Span {
+        Span {
+            file: self.file.clone(),
+            start: self.start,
+            end: other.end,
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    /// Formats as `file:line:column`, leaving out the file if unknown.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}:", file.display())?;
+        }
+        write!(f, "{}:{}", self.start.line, self.start.column)
+    }
+}
+
+/// A single point in an input file.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct Position {
+    /// In bytes, from the start of the file.
+    pub offset: usize,
+    /// 1-based.
+    pub line: usize,
+    /// 1-based, counted in chars.
+    pub column: usize,
+}
+
+/// Translates byte offsets into a source to lines and columns.
+#[derive(Debug)]
+pub struct LineIndex<'a> {
+    source: &'a str,
+    /// Byte offset of the start of every line.
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(source: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(offset, _)| offset + 1))
+            .collect();
+        Self { source, starts }
+    }
+
+    pub fn position(&self, offset: usize) -> Position {
+        let mut offset = offset.min(self.source.len());
+        while !self.source.is_char_boundary(offset) {
+            offset -= 1;
+        }
+
+        let line = self.starts.partition_point(|start| *start <= offset);
+        let line_start = self.starts[line - 1];
+        Position {
+            offset,
+            line,
+            column: self.source[line_start..offset].chars().count() + 1,
+        }
+    }
+
+    /// The byte offset of the given 1-based line and column, clamped to the source.
+    pub fn offset(&self, line: usize, column: usize) -> usize {
+        let Some(line_start) = line.checked_sub(1).and_then(|line| self.starts.get(line)) else {
+            return self.source.len();
+        };
+
+        self.source[*line_start..]
+            .char_indices()
+            .nth(column.saturating_sub(1))
+            .map_or(self.source.len(), |(offset, _)| line_start + offset)
+    }
+
+    pub fn span(&self, range: Range<usize>) -> Span {
+        Span {
+            file: None,
+            start: self.position(range.start),
+            end: self.position(range.end),
+        }
+    }
+
+    /// The span of `part`, which has to be a slice of the source itself.
+    pub fn span_of(&self, part: &str) -> Span {
+        let start = (part.as_ptr() as usize)
+            .checked_sub(self.source.as_ptr() as usize)
+            .filter(|start| start + part.len() <= self.source.len())
+            .expect("part to be a slice of the source");
+        self.span(start..start + part.len())
+    }
+}
+
+/// An error pointing at the place in the input that caused it.
+#[derive(Debug)]
+pub struct SpannedError {
+    pub span: Span,
+    error: Box<dyn Error + Send + Sync>,
+}
+
+impl SpannedError {
+    pub fn new(span: Span, error: impl Into<Box<dyn Error + Send + Sync>>) -> Self {
+        Self {
+            span,
+            error: error.into(),
+        }
+    }
+
+    /// Sets the file of the span, if it doesn't have one yet.
+    pub fn in_file(mut self, path: &Path) -> Self {
+        self.span.file.get_or_insert_with(|| path.to_path_buf());
+        self
+    }
+}
+
+impl fmt::Display for SpannedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.error.fmt(f)
+    }
+}
+
+impl Error for SpannedError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.error.source()
+    }
+}
@@ -0,0 +1,80 @@
+//! How a trigger is matched against the text in front of the cursor
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::regex::Dialect;
+
+/// Where a trigger is allowed to match, or if it's even a regex.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum TriggerKind {
+    /// Matches only if preceded by whitespace or the start of the line.
+    #[default]
+    Literal,
+    /// Matches if the trigger starts at a word boundary.
+    Word,
+    /// Matches anywhere, including in the middle of a word.
+    InWord,
+    /// The trigger is a regular expression matched against the text in front of the cursor.
+    Regex(RegexFlags),
+}
+
+impl TriggerKind {
+    pub fn is_regex(&self) -> bool {
+        matches!(self, Self::Regex(_))
+    }
+}
+
+/// Flags modifying how a regex trigger is matched. Both JavaScript and Python spell them using
+/// the same letters, which is what [`RegexFlags::from_letters`] and [`RegexFlags::letters`]
+/// work with. Neither cares about the dialect.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+pub struct RegexFlags {
+    /// `i`
+    pub ignore_case: bool,
+    /// `m`, `^` and `$` match at line boundaries.
+    pub multi_line: bool,
+    /// `s`, `.` matches newlines, too.
+    pub dot_all: bool,
+    /// `u`
+    pub unicode: bool,
+    /// Which engine the regex is written for, backends using another one
+    /// [translate](crate::regex::translate) it. If unknown, it's taken as-is.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dialect: Option<Dialect>,
+}
+
+impl RegexFlags {
+    /// Parses flags from their letters. Returns the first letter that isn't a known flag as
+    /// error.
+    pub fn from_letters(letters: &str) -> Result<Self, char> {
+        let mut flags = Self::default();
+
+        for letter in letters.chars() {
+            let flag = match letter {
+                'i' => &mut flags.ignore_case,
+                'm' => &mut flags.multi_line,
+                's' => &mut flags.dot_all,
+                'u' => &mut flags.unicode,
+                unknown => return Err(unknown),
+            };
+            *flag = true;
+        }
+
+        Ok(flags)
+    }
+
+    /// Returns the letters of all set flags, in a stable order. Empty if none are set.
+    pub fn letters(&self) -> String {
+        [
+            (self.ignore_case, 'i'),
+            (self.multi_line, 'm'),
+            (self.dot_all, 's'),
+            (self.unicode, 'u'),
+        ]
+        .into_iter()
+        .filter_map(|(set, letter)| set.then_some(letter))
+        .collect()
+    }
+}
@@ -0,0 +1,52 @@
+//! Comments and blank lines, which don't change what snippets do but are worth keeping
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// One line that isn't part of any snippet.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Trivia {
+    /// A comment, without its comment marker and the space following it.
+    Comment(String),
+    /// An empty line in addition to whatever separation the format puts between snippets
+    /// anyway.
+    Blank,
+}
+
+/// Writes the trivia as lines, using the given comment marker and indenting every line.
+pub(crate) fn write_trivia<'a>(
+    output: &mut String,
+    trivia: impl IntoIterator<Item = &'a Trivia>,
+    marker: &str,
+    indent: &str,
+) {
+    for line in trivia {
+        match line {
+            Trivia::Comment(comment) => {
+                // comments coming from the IR directly could span multiple lines
+                for comment in comment.split('\n') {
+                    output.push_str(indent);
+                    write_comment(output, comment, marker);
+                    output.push('\n');
+                }
+            }
+            Trivia::Blank => output.push('\n'),
+        }
+    }
+}
+
+/// Writes a single comment without any newline.
+pub(crate) fn write_comment(output: &mut String, comment: &str, marker: &str) {
+    output.push_str(marker);
+    if !comment.is_empty() {
+        output.push(' ');
+        output.push_str(comment);
+    }
+}
+
+/// Parses the text after a comment marker into a comment.
+pub(crate) fn comment(text: &str) -> Trivia {
+    let text = text.strip_prefix(' ').unwrap_or(text);
+    Trivia::Comment(text.trim_end().to_string())
+}
@@ -1,6 +1,6 @@
 use std::path::PathBuf;
 
-use clap::{value_parser, Arg, ArgGroup, ArgMatches, Command};
+use clap::{value_parser, Arg, ArgAction, ArgGroup, ArgMatches, Command};
 
 use crate::Backend;
 
@@ -27,7 +27,29 @@ pub fn cmdline(backends: &[Box<dyn Backend>]) -> ArgMatches {
             );
             out_args = out_args.arg(name_out);
         }
+
+        cmd = cmd.args(backend.args());
     }
 
+    cmd = cmd.arg(
+        Arg::new("resolve-extends")
+            .long("resolve-extends")
+            .action(ArgAction::SetTrue)
+            .help("Inline the snippets of extended filetypes, read from next to the input"),
+    );
+    cmd = cmd.arg(
+        Arg::new("warnings-json")
+            .long("warnings-json")
+            .value_name("PATH")
+            .value_parser(value_parser!(PathBuf))
+            .help("Also write all warnings as a JSON array to the given file"),
+    );
+    cmd = cmd.arg(
+        Arg::new("deny-warnings")
+            .long("deny-warnings")
+            .action(ArgAction::SetTrue)
+            .help("Fail without writing anything if a snippet can't be converted exactly"),
+    );
+
     cmd.group(in_args).group(out_args).get_matches()
 }
@@ -0,0 +1,94 @@
+//! Well-known values snippets can insert, like the file name or the current date
+//!
+//! Every format spells these differently, if it has them at all. UltiSnips evaluates Vim script
+//! in backticks, VS Code and other LSP-style formats have `$TM_FILENAME` and friends. Backends
+//! recognise their own spelling and turn it into a [`Variable`], so converting rewrites them
+//! instead of copying code the target can't run.
+//!
+//! The selection isn't in here, it's [`Node::Visual`](crate::body::Node::Visual).
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// A value known when the snippet expands.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Variable {
+    /// Name of the current file, like `main.tex`.
+    FileName,
+    /// Name of the current file without its extension, like `main`.
+    FileStem,
+    /// Absolute path of the current file.
+    FilePath,
+    /// Absolute path of the directory the current file is in.
+    Directory,
+    /// 1-based number of the line the cursor is on.
+    LineNumber,
+    /// Contents of the system clipboard.
+    Clipboard,
+    /// Like `2023`.
+    Year,
+    /// Like `23`.
+    YearShort,
+    /// Like `07`.
+    Month,
+    /// Like `July`.
+    MonthName,
+    /// Like `Jul`.
+    MonthNameShort,
+    /// Day of the month, like `09`.
+    Day,
+    /// Like `Monday`.
+    DayName,
+    /// Like `Mon`.
+    DayNameShort,
+    /// In 24-hour format, like `14`.
+    Hour,
+    Minute,
+    Second,
+}
+
+impl Variable {
+    pub const ALL: [Variable; 17] = [
+        Variable::FileName,
+        Variable::FileStem,
+        Variable::FilePath,
+        Variable::Directory,
+        Variable::LineNumber,
+        Variable::Clipboard,
+        Variable::Year,
+        Variable::YearShort,
+        Variable::Month,
+        Variable::MonthName,
+        Variable::MonthNameShort,
+        Variable::Day,
+        Variable::DayName,
+        Variable::DayNameShort,
+        Variable::Hour,
+        Variable::Minute,
+        Variable::Second,
+    ];
+
+    /// The name VS Code and LSP-style snippets use, without the `$`.
+    pub fn lsp_name(self) -> &'static str {
+        match self {
+            Variable::FileName => "TM_FILENAME",
+            Variable::FileStem => "TM_FILENAME_BASE",
+            Variable::FilePath => "TM_FILEPATH",
+            Variable::Directory => "TM_DIRECTORY",
+            Variable::LineNumber => "TM_LINE_NUMBER",
+            Variable::Clipboard => "CLIPBOARD",
+            Variable::Year => "CURRENT_YEAR",
+            Variable::YearShort => "CURRENT_YEAR_SHORT",
+            Variable::Month => "CURRENT_MONTH",
+            Variable::MonthName => "CURRENT_MONTH_NAME",
+            Variable::MonthNameShort => "CURRENT_MONTH_NAME_SHORT",
+            Variable::Day => "CURRENT_DATE",
+            Variable::DayName => "CURRENT_DAY_NAME",
+            Variable::DayNameShort => "CURRENT_DAY_NAME_SHORT",
+            Variable::Hour => "CURRENT_HOUR",
+            Variable::Minute => "CURRENT_MINUTE",
+            Variable::Second => "CURRENT_SECOND",
+        }
+    }
+}